@@ -0,0 +1,30 @@
+//! The client half of a trusted device, see `auth_common::device`.
+use alloc::vec::Vec;
+
+use auth_common::device::DeviceKey;
+
+use crate::{ClientError, encode};
+
+/// Key of a device the server trusts. Keep it on the device, e.g. with
+/// `export`.
+pub struct TrustedDevice {
+    pub(crate) key: DeviceKey,
+}
+impl TrustedDevice {
+    /// `shared_key` is the key of the login the server trusted the device in,
+    /// from `Proof::finish`.
+    pub fn new(shared_key: &[u8; 512]) -> TrustedDevice {
+        TrustedDevice { key: DeviceKey::new(shared_key) }
+    }
+    /// Same as the id the server lists the device under.
+    pub fn id(&self) -> [u8; 16] {
+        self.key.id()
+    }
+    /// The `DeviceProof` for a later login with `shared_key`. No login message
+    /// carries it, the application sends it its own way, see
+    /// `auth_server::device`. To skip TOTP it has to go out with the
+    /// `AuthReq`, so make it for `Proof::unverified_key`.
+    pub fn proof(&self, shared_key: &[u8; 512]) -> Result<Vec<u8>, ClientError> {
+        encode(&self.key.proof(shared_key))
+    }
+}
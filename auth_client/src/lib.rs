@@ -1,150 +1,252 @@
+//! Client side of the login.
+//!
+//! `Login` and `Proof` take the replies of the server as framed bytes and
+//! return the next request. Randomness comes from any `rand_core::CryptoRng`.
+//! Both can be exported as an encrypted blob and resumed later, see `saved`.
+//! Once logged in, `Channel` encrypts messages to and from the server and
+//! `Signer` signs requests. With the ticket the server sends after a login,
+//! `Resumption` logs in again later without the password. A `TrustedDevice`
+//! proves to the server that a login comes from a device the user trusted.
+//! To reset a lost password, log in with `RecoveryKey::as_str` as the
+//! password and seal the `register` request with the `Channel` of that login.
+//!
+//! The JS bindings live in `wasm`, behind the `wasm` feature:
+//! `wasm-pack build -- --features wasm`.
 #![no_std]
 
-extern crate wee_alloc;
+extern crate alloc;
 
-// Use `wee_alloc` as the global allocator.
-#[global_allocator]
-static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+mod device;
+mod resume;
+pub mod saved;
+mod signing;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-use core::{any::type_name, mem::MaybeUninit};
+use alloc::{vec, vec::Vec};
 
-use argon2::{Argon2, Block, Params, ParamsBuilder};
-use auth_common::{AuthReq, Data, PreAuthReq, PreAuthResp, RegisterReq};
-use blake2::Blake2b512;
-use js_sys::{ArrayBuffer, Promise, Uint8Array};
-use srp::{
-    A2, DigestNum, Encoding, U4096, Uint,
-    client::{SrpClient4096, SrpClientVerifier4096},
+use auth_common::{
+    AuthResponse, ClientChallenge, ClientHashed, ClientProof, ClientStart, ErrorResp, Frame,
+    FrameError, HandshakeError, Message, MessageType, PreAuthResp, RegisterReq, TotpChallenge,
+    TotpReq, encode_message,
 };
-use wasm_bindgen::prelude::*;
+pub use auth_common::{ErrorCode, Suite, recovery::RecoveryKey};
+pub use auth_common::channel::{Channel, ChannelError, Role};
+pub use device::TrustedDevice;
+pub use resume::{Resumption, Resuming};
+pub use signing::{Signature, Signer};
+use rand_core::CryptoRng;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClientError {
+    /// The reply of the server could not be decoded.
+    Frame(FrameError),
+    /// The server answered with an `ErrorResp`.
+    Rejected(ErrorResp),
+    Handshake(HandshakeError),
+    /// The request does not fit into a frame, the username is too long.
+    Encode,
+}
+impl From<FrameError> for ClientError {
+    fn from(e: FrameError) -> Self {
+        ClientError::Frame(e)
+    }
+}
+impl From<HandshakeError> for ClientError {
+    fn from(e: HandshakeError) -> Self {
+        ClientError::Handshake(e)
+    }
+}
+
+/// Encode `msg` as a framed message.
+fn encode<'a, M: Message<'a>>(msg: &M) -> Result<Vec<u8>, ClientError> {
+    let mut buf = vec![0; M::FRAME_SIZE];
+    let len = encode_message(msg, &mut buf).ok_or(ClientError::Encode)?.len();
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Decode a framed `M`. An `ErrorResp` from the server is returned as `ClientError::Rejected`.
+fn decode<'a, M: Message<'a>>(data: &'a [u8]) -> Result<M, ClientError> {
+    let frame = match Frame::decode(data)? {
+        (frame, []) => frame,
+        _ => return Err(FrameError::Malformed.into()),
+    };
+    if frame.kind == MessageType::ErrorResp && M::TYPE != MessageType::ErrorResp {
+        return Err(ClientError::Rejected(frame.parse()?));
+    }
+    Ok(frame.parse()?)
+}
+
+/// A login waiting for the `PreAuthResp`.
+pub struct Login {
+    start: ClientStart,
+}
+impl Login {
+    /// Computes `A` right away. Create the login before the password is
+    /// entered to take this off the visible delay.
+    pub fn new<R: CryptoRng + ?Sized>(rng: &mut R) -> Login {
+        let mut secret = [0; 512];
+        rng.fill_bytes(&mut secret);
+        Login::from_secret(secret)
+    }
+    /// `secret` has to come from a cryptographic RNG.
+    pub fn from_secret(secret: [u8; 512]) -> Login {
+        Login { start: ClientStart::new(secret) }
+    }
+    /// The `PreAuthReq` to send.
+    pub fn request(&self, username: &str) -> Result<Vec<u8>, ClientError> {
+        encode(&self.start.message(username))
+    }
+    /// Process the `PreAuthResp`. This runs the password hasher and takes a while.
+    pub fn process(self, resp: &[u8], username: &str, password: &str) -> Result<Proof, ClientError> {
+        self.receive(resp)?.hash_password(username, password).compute_proof()
+    }
+    /// Decode the `PreAuthResp`. Continue with `Challenge::hash_password`
+    /// and `Hashed::compute_proof`, both take a while.
+    pub fn receive(self, resp: &[u8]) -> Result<Challenge, ClientError> {
+        let resp: PreAuthResp = decode(resp)?;
+        Ok(Challenge { challenge: self.start.receive(&resp) })
+    }
+}
+
+/// A login that received the `PreAuthResp`.
+pub struct Challenge {
+    challenge: ClientChallenge,
+}
+impl Challenge {
+    pub fn hash_password(self, username: &str, password: &str) -> Hashed {
+        Hashed { hashed: self.challenge.hash_password(username, password) }
+    }
+}
 
-type Digest = Blake2b512;
+/// A login that ran the password hasher.
+pub struct Hashed {
+    hashed: ClientHashed,
+}
+impl Hashed {
+    pub fn compute_proof(self) -> Result<Proof, ClientError> {
+        Ok(Proof { proof: self.hashed.compute_proof()? })
+    }
+}
 
-#[wasm_bindgen]
-unsafe extern "C" {
-    type Crypto;
-    #[wasm_bindgen(method)]
-    fn getRandomValues(this: &Crypto, buffer: Uint8Array) -> ArrayBuffer;
+/// A login waiting for the `AuthResponse`.
+pub struct Proof {
+    proof: ClientProof,
+}
+impl Proof {
+    /// The `AuthReq` to send.
+    pub fn request(&self) -> Result<Vec<u8>, ClientError> {
+        encode(&self.proof.message())
+    }
+    /// The shared key, without checking the proof of the server. Prefer `finish`.
+    pub fn unverified_key(&self) -> [u8; 512] {
+        self.proof.unverified_key()
+    }
+    /// The number of digits if the server answered the `AuthReq` with a
+    /// `TotpChallenge` instead of the `AuthResponse`.
+    pub fn totp_challenge(&self, resp: &[u8]) -> Result<Option<u8>, ClientError> {
+        match Frame::decode(resp)? {
+            (frame, []) if frame.kind == MessageType::TotpChallenge => {
+                Ok(Some(frame.parse::<TotpChallenge>()?.digits))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// The `TotpReq` with `code` from the authenticator app, in reply to the
+    /// `TotpChallenge` in `resp`. The server answers it with the `AuthResponse`.
+    pub fn totp_request(&self, resp: &[u8], code: u32) -> Result<Vec<u8>, ClientError> {
+        let challenge: TotpChallenge = decode(resp)?;
+        encode(&TotpReq { key: challenge.key, code })
+    }
+    /// Check the `AuthResponse` and return the shared key.
+    pub fn finish(self, resp: &[u8]) -> Result<[u8; 512], ClientError> {
+        let resp: AuthResponse = decode(resp)?;
+        Ok(self.proof.finish(&resp)?)
+    }
+}
+
+/// Build a `RegisterReq` for a new password.
+pub fn register<R: CryptoRng + ?Sized>(
+    rng: &mut R,
+    username: &str,
+    password: &str,
+    suite: Suite,
+) -> Result<Vec<u8>, ClientError> {
+    let mut salt = [0; 32];
+    rng.fill_bytes(&mut salt);
+    let verifier = suite.compute_verifier(username.as_bytes(), password.as_bytes(), &salt);
+    encode(&RegisterReq { username, salt, verifier: &verifier, suite })
+}
+
+/// Decode an `ErrorResp` sent by the server.
+pub fn decode_error(resp: &[u8]) -> Result<ErrorResp, ClientError> {
+    decode(resp)
 }
 
-fn rand_buf<const N: usize>(crypto: &Crypto) -> Result<[u8; N], JsValue> {
-    let bytes = unsafe {
-        let mut buf = MaybeUninit::<[u8; N]>::uninit();
-        let filled = crypto.getRandomValues(Uint8Array::view_mut_raw(buf.as_mut_ptr().cast(), N));
-        let len = filled.byte_length();
-        if len as usize != N {
-            return Err(JsValue::from_str("not enough data"));
+#[test]
+fn test_login() {
+    use auth_common::{AuthReq, PreAuthReq, ServerChallenge, decode_message};
+
+    // not random at all, but enough to drive the handshake
+    struct Counter(u8);
+    impl rand_core::RngCore for Counter {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
         }
-        buf.assume_init()
-    };
-    Ok(bytes)
-}
-
-#[wasm_bindgen]
-pub struct Step1 {
-    client: SrpClient4096<A2, Digest>,
-}
-
-#[wasm_bindgen]
-impl Step1 {
-    #[wasm_bindgen(constructor)]
-    pub fn new(crypto: &Crypto) -> Result<Step1, JsValue> {
-        let a = U4096::from_le_bytes(rand_buf(crypto)?);
-        let client = SrpClient4096::new(a);
-
-        Ok(Step1 { client })
-    }
-    pub fn req(&self, username: &str) -> Result<Uint8Array, JsValue> {
-        let a_pub = self.client.compute_a_pub();
-        encode(
-            &mut [0; 1024],
-            &PreAuthReq {
-                a_pub: a_pub.to_le_bytes().into(),
-                username,
-            },
-        )
-    }
-    pub fn auth(
-        &self,
-        username: &str,
-        password: &str,
-        resp: &Uint8Array,
-    ) -> Result<Step2, JsValue> {
-        let mut buf = [0; 1024];
-        let resp: PreAuthResp = decode(&mut buf, resp).ok_or(JsValue::from_str("invalid data"))?;
-        let verifier = self
-            .client
-            .process_reply(
-                username.as_bytes(),
-                password.as_bytes(),
-                &resp.salt,
-                &U4096::from_le_bytes(resp.b_pub.into()),
-            )
-            .map_err(|_| JsValue::from_str("authentication failed"))?;
-
-        Ok(Step2 {
-            verifier,
-            key: resp.key,
-        })
-    }
-}
-
-#[wasm_bindgen]
-pub fn register(crypto: &Crypto, username: &str, password: &str) -> Result<Uint8Array, JsValue> {
-    let salt = rand_buf::<32>(crypto)?;
-    let verifier = SrpClient4096::<A2, Digest>::compute_verifier(
-        username.as_bytes(),
-        &password.as_bytes(),
-        &salt,
-    );
-
-    Ok(encode(
-        &mut [0; 1024],
-        &RegisterReq {
-            username,
-            salt: salt.into(),
-            verifier: verifier.to_le_bytes().into(),
-        },
-    )?)
-}
-
-fn encode<'a, D: Data<'a>>(buf: &'a mut [u8], value: &'a D) -> Result<Uint8Array, JsValue> {
-    let data = value
-        .encode(buf)
-        .ok_or(JsValue::from_str(type_name::<D>()))?;
-    Ok(Uint8Array::from(data))
-}
-
-fn decode<'a, D: Data<'a>>(buf: &'a mut [u8], data: &Uint8Array) -> Option<D> {
-    let len = data.length() as usize;
-    let (dst, _) = buf.split_at_mut_checked(len)?;
-    data.copy_to(dst);
-
-    let (val, _) = D::decode(dst)?;
-    Some(val)
-}
-
-#[wasm_bindgen]
-pub struct Step2 {
-    verifier: SrpClientVerifier4096<Digest>,
-    key: [u8; 8],
-}
-
-#[wasm_bindgen]
-impl Step2 {
-    pub fn req(&self) -> Result<Uint8Array, JsValue> {
-        encode(
-            &mut [0; AuthReq::SIZE],
-            &AuthReq {
-                proof: (*self.verifier.proof()).into(),
-                key: self.key,
-            },
-        )
-    }
-    pub fn get_key(&self) -> Uint8Array {
-        let key_bytes = self.verifier.key().to_le_bytes();
-        let out = Uint8Array::new_with_length(key_bytes.len() as u32);
-        out.copy_from(&key_bytes);
-        out
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+        fn fill_bytes(&mut self, dst: &mut [u8]) {
+            for b in dst {
+                self.0 = self.0.wrapping_add(1);
+                *b = self.0;
+            }
+        }
+    }
+    impl CryptoRng for Counter {}
+    let mut rng = Counter(0);
+
+    let req = register(&mut rng, "user", "password", Suite::Blake2bArgon2d).unwrap();
+    let user: RegisterReq = decode_message(&req).unwrap();
+
+    let login = Login::new(&mut rng);
+    let req = login.request("user").unwrap();
+    let req: PreAuthReq = decode_message(&req).unwrap();
+    let server = ServerChallenge::new(&req, user.salt, user.verifier, user.suite, [7; 512], [1; 8]).unwrap();
+
+    let proof = login.process(&encode(&server.message()).unwrap(), "user", "password").unwrap();
+    let req = proof.request().unwrap();
+    let server = server.process(&decode_message::<AuthReq>(&req).unwrap()).unwrap();
+    let key = proof.finish(&encode(&server.message()).unwrap()).unwrap();
+    assert_eq!(key, server.key());
+
+    // resume a login from exported state
+    use saved::StateError;
+    let key = [9; 32];
+    let login = Login::new(&mut rng);
+    let blob = login.export(&mut rng, &key, 100);
+    assert_eq!(Login::import(&blob, &[8; 32], 50).err(), Some(StateError::Invalid));
+    assert_eq!(Login::import(&blob, &key, 100).err(), Some(StateError::Expired));
+    assert_eq!(Proof::import(&blob, &key, 50).err(), Some(StateError::WrongKind));
+    let mut tampered = blob.clone();
+    tampered[1] ^= 1; // push out the expiry
+    assert_eq!(Login::import(&tampered, &key, 50).err(), Some(StateError::Invalid));
+
+    let login = Login::import(&blob, &key, 50).unwrap();
+    let req = login.request("user").unwrap();
+    let req: PreAuthReq = decode_message(&req).unwrap();
+    let server = ServerChallenge::new(&req, user.salt, user.verifier, user.suite, [7; 512], [1; 8]).unwrap();
+    let proof = login.process(&encode(&server.message()).unwrap(), "user", "password").unwrap();
+    let blob = proof.export(&mut rng, &key, 100);
+    let proof = Proof::import(&blob, &key, 50).unwrap();
+    let server = server.process(&decode_message::<AuthReq>(&proof.request().unwrap()).unwrap()).unwrap();
+    assert_eq!(proof.finish(&encode(&server.message()).unwrap()).unwrap(), server.key());
+
+    let err = encode(&ErrorResp::new(ErrorCode::TooManyAttempts)).unwrap();
+    let login = Login::new(&mut rng);
+    match login.process(&err, "user", "password") {
+        Err(ClientError::Rejected(resp)) => assert_eq!(resp.code, ErrorCode::TooManyAttempts),
+        _ => panic!(),
     }
 }
@@ -0,0 +1,64 @@
+//! Resuming a login with a ticket from the server, without the password.
+//! The exchange is described in `auth_common::resume`.
+use alloc::vec::Vec;
+
+use auth_common::{
+    Bytes, HandshakeError, ResumeReq, ResumeResp, Ticket,
+    resume::{DhShare, ResumeSecret},
+};
+use rand_core::CryptoRng;
+
+use crate::{ClientError, decode, encode};
+
+/// A ticket and the secret that goes with it. Keep it, e.g. with `export`,
+/// to resume the login later.
+pub struct Resumption {
+    pub(crate) secret: ResumeSecret,
+    pub(crate) ticket: Vec<u8>,
+    pub(crate) expires: u64,
+}
+impl Resumption {
+    /// `shared_key` comes from `Proof::finish`, `ticket` is the `Ticket` the
+    /// server sent after the login.
+    pub fn new(shared_key: &[u8; 512], ticket: &[u8]) -> Result<Resumption, ClientError> {
+        let msg: Ticket = decode(ticket)?;
+        Ok(Resumption { secret: ResumeSecret::new(shared_key), ticket: msg.ticket.0.into(), expires: msg.expires })
+    }
+    /// Seconds since the Unix epoch after which the server refuses the ticket.
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+    /// Start resuming. A ticket works once, so this uses it up.
+    pub fn start<R: CryptoRng + ?Sized>(self, rng: &mut R) -> Resuming {
+        let mut secret = [0; 32];
+        rng.fill_bytes(&mut secret);
+        let share = DhShare::new(secret);
+        let proof = self.secret.client_proof(&self.ticket, share.public());
+        Resuming { resumption: self, share, proof }
+    }
+}
+
+/// A resumption waiting for the `ResumeResp`.
+pub struct Resuming {
+    resumption: Resumption,
+    share: DhShare,
+    proof: [u8; 32],
+}
+impl Resuming {
+    /// The `ResumeReq` to send.
+    pub fn request(&self) -> Result<Vec<u8>, ClientError> {
+        encode(&ResumeReq { ticket: Bytes(&self.resumption.ticket), share: self.share.public(), proof: self.proof })
+    }
+    /// Check the proof of the server. Returns the new shared key and the
+    /// `Resumption` for next time, the old secret is gone.
+    pub fn finish(self, resp: &[u8]) -> Result<([u8; 512], Resumption), ClientError> {
+        let resp: ResumeResp = decode(resp)?;
+        let Resuming { resumption, share, .. } = self;
+        if !resumption.secret.verify_server(&resumption.ticket, share.public(), resp.share, &resp.proof) {
+            return Err(HandshakeError::BadProof.into());
+        }
+        let dh = share.agree(resp.share)?;
+        let (key, secret) = resumption.secret.next(share.public(), resp.share, &dh);
+        Ok((key, Resumption { secret, ticket: resp.ticket.0.into(), expires: resp.expires }))
+    }
+}
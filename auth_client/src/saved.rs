@@ -0,0 +1,205 @@
+//! Export of an unfinished login, so it can be resumed after the page or
+//! worker that started it is gone, and of a `Resumption` or `TrustedDevice`
+//! between visits.
+//!
+//! The state is sealed with ChaCha20-Poly1305 under a key of the caller:
+//!
+//! | kind | expires (u64 LE) | nonce (12) | ciphertext + tag |
+//!
+//! `kind` and `expires` are authenticated as associated data. Times are
+//! seconds since the Unix epoch.
+use alloc::{vec, vec::Vec};
+
+use auth_common::{
+    Bytes, ClientProof, ClientProofParts, ClientStart, Data, Suite,
+    device::DeviceKey,
+    resume::{MAX_TICKET_LEN, ResumeSecret},
+};
+use chacha20poly1305::{
+    ChaCha20Poly1305, KeyInit,
+    aead::{Aead, Payload},
+};
+use rand_core::CryptoRng;
+
+use crate::{Login, Proof, Resumption, TrustedDevice};
+
+const HEADER: usize = 1 + 8;
+const NONCE: usize = 12;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// Too short to be an exported state.
+    Malformed,
+    /// The state of a different step, e.g. a `Proof` passed to `Login::import`.
+    WrongKind,
+    /// The state is past its expiry time.
+    Expired,
+    /// Wrong key, or the blob was modified.
+    Invalid,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+enum Kind {
+    Login = 1,
+    Proof = 2,
+    Resumption = 3,
+    Device = 4,
+}
+
+#[derive(Data)]
+struct SavedLogin {
+    secret: [u8; 512],
+    a_pub: [u8; 512],
+}
+
+#[derive(Data)]
+struct SavedProof {
+    suite: Suite,
+    proof: [u8; 64],
+    server_proof: [u8; 64],
+    shared_key: [u8; 512],
+    key: [u8; 8],
+}
+
+#[derive(Data)]
+struct SavedResumption<'a> {
+    secret: [u8; 32],
+    ticket: Bytes<'a, MAX_TICKET_LEN>,
+}
+
+#[derive(Data)]
+struct SavedDevice {
+    id: [u8; 16],
+    key: [u8; 32],
+}
+
+fn seal<'a, R, T>(rng: &mut R, key: &[u8; 32], kind: Kind, expires: u64, state: &T) -> Vec<u8>
+where
+    R: CryptoRng + ?Sized,
+    T: Data<'a>,
+{
+    let mut plain = vec![0; T::SIZE];
+    let plain = state.encode(&mut plain).expect("`SIZE` bounds the encoding");
+
+    let mut out = Vec::with_capacity(HEADER + NONCE + plain.len() + 16);
+    out.push(kind as u8);
+    out.extend_from_slice(&expires.to_le_bytes());
+    let mut nonce = [0; NONCE];
+    rng.fill_bytes(&mut nonce);
+    out.extend_from_slice(&nonce);
+
+    let cipher = ChaCha20Poly1305::new(key.into());
+    let sealed = cipher
+        .encrypt(&nonce.into(), Payload { msg: plain, aad: &out[..HEADER] })
+        .expect("plaintext is short");
+    out.extend_from_slice(&sealed);
+    out
+}
+
+fn open(blob: &[u8], key: &[u8; 32], kind: Kind, now: u64) -> Result<Vec<u8>, StateError> {
+    let (header, rest) = blob.split_at_checked(HEADER).ok_or(StateError::Malformed)?;
+    let (nonce, sealed) = rest.split_at_checked(NONCE).ok_or(StateError::Malformed)?;
+    let expires = u64::from_le_bytes(header[1..].try_into().unwrap());
+
+    // only trust the header once the tag checked out
+    let cipher = ChaCha20Poly1305::new(key.into());
+    let plain = cipher
+        .decrypt(nonce.into(), Payload { msg: sealed, aad: header })
+        .map_err(|_| StateError::Invalid)?;
+    if header[0] != kind as u8 {
+        return Err(StateError::WrongKind);
+    }
+    if now >= expires {
+        return Err(StateError::Expired);
+    }
+    Ok(plain)
+}
+
+impl Login {
+    /// Encrypt the state under `key`. `import` refuses it from `expires` on.
+    pub fn export<R: CryptoRng + ?Sized>(&self, rng: &mut R, key: &[u8; 32], expires: u64) -> Vec<u8> {
+        let (secret, a_pub) = self.start.to_parts();
+        seal(rng, key, Kind::Login, expires, &SavedLogin { secret, a_pub })
+    }
+    /// Restore a login saved with `export`. `now` is the current time.
+    pub fn import(blob: &[u8], key: &[u8; 32], now: u64) -> Result<Login, StateError> {
+        let plain = open(blob, key, Kind::Login, now)?;
+        let saved = SavedLogin::decode_exact(&plain).ok_or(StateError::Invalid)?;
+        Ok(Login { start: ClientStart::from_parts(saved.secret, saved.a_pub) })
+    }
+}
+
+impl Proof {
+    /// Encrypt the state under `key`. `import` refuses it from `expires` on.
+    pub fn export<R: CryptoRng + ?Sized>(&self, rng: &mut R, key: &[u8; 32], expires: u64) -> Vec<u8> {
+        let ClientProofParts { suite, proof, server_proof, shared_key, key: login_key } = self.proof.to_parts();
+        let saved = SavedProof { suite, proof, server_proof, shared_key, key: login_key };
+        seal(rng, key, Kind::Proof, expires, &saved)
+    }
+    /// Restore a proof saved with `export`. `now` is the current time.
+    pub fn import(blob: &[u8], key: &[u8; 32], now: u64) -> Result<Proof, StateError> {
+        let plain = open(blob, key, Kind::Proof, now)?;
+        let SavedProof { suite, proof, server_proof, shared_key, key } =
+            SavedProof::decode_exact(&plain).ok_or(StateError::Invalid)?;
+        let proof = ClientProof::from_parts(ClientProofParts { suite, proof, server_proof, shared_key, key });
+        Ok(Proof { proof })
+    }
+}
+
+impl Resumption {
+    /// Encrypt the ticket and secret under `key`. `import` refuses it once
+    /// the ticket expired.
+    pub fn export<R: CryptoRng + ?Sized>(&self, rng: &mut R, key: &[u8; 32]) -> Vec<u8> {
+        let saved = SavedResumption { secret: *self.secret.as_bytes(), ticket: Bytes(&self.ticket) };
+        seal(rng, key, Kind::Resumption, self.expires, &saved)
+    }
+    /// Restore a resumption saved with `export`. `now` is the current time.
+    pub fn import(blob: &[u8], key: &[u8; 32], now: u64) -> Result<Resumption, StateError> {
+        let plain = open(blob, key, Kind::Resumption, now)?;
+        let saved = SavedResumption::decode_exact(&plain).ok_or(StateError::Invalid)?;
+        let expires = u64::from_le_bytes(blob[1..HEADER].try_into().unwrap());
+        Ok(Resumption { secret: ResumeSecret::from_bytes(saved.secret), ticket: saved.ticket.0.into(), expires })
+    }
+}
+
+impl TrustedDevice {
+    /// Encrypt the device key under `key`. `import` refuses it from `expires`
+    /// on, e.g. when the server stops trusting the device.
+    pub fn export<R: CryptoRng + ?Sized>(&self, rng: &mut R, key: &[u8; 32], expires: u64) -> Vec<u8> {
+        let saved = SavedDevice { id: self.key.id(), key: *self.key.key() };
+        seal(rng, key, Kind::Device, expires, &saved)
+    }
+    /// Restore a device key saved with `export`. `now` is the current time.
+    pub fn import(blob: &[u8], key: &[u8; 32], now: u64) -> Result<TrustedDevice, StateError> {
+        let plain = open(blob, key, Kind::Device, now)?;
+        let saved = SavedDevice::decode_exact(&plain).ok_or(StateError::Invalid)?;
+        Ok(TrustedDevice { key: DeviceKey::from_parts(saved.id, saved.key) })
+    }
+}
+
+#[test]
+fn test_seal_size() {
+    struct Zeros;
+    impl rand_core::RngCore for Zeros {
+        fn next_u32(&mut self) -> u32 {
+            0
+        }
+        fn next_u64(&mut self) -> u64 {
+            0
+        }
+        fn fill_bytes(&mut self, dst: &mut [u8]) {
+            dst.fill(0);
+        }
+    }
+    impl CryptoRng for Zeros {}
+
+    // larger than any state here, sealing sizes the buffer from `T`
+    #[derive(Data)]
+    struct Large {
+        data: [u8; 4096],
+    }
+    let blob = seal(&mut Zeros, &[1; 32], Kind::Device, 100, &Large { data: [2; 4096] });
+    let plain = open(&blob, &[1; 32], Kind::Device, 50).unwrap();
+    assert_eq!(Large::decode_exact(&plain).unwrap().data, [2; 4096]);
+}
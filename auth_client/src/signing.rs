@@ -0,0 +1,22 @@
+//! Signing requests made after the login. The scheme is described in
+//! `auth_common::signing`, `auth_server::signing::Verifier` checks them.
+pub use auth_common::signing::Signature;
+use auth_common::signing::SigningKey;
+use rand_core::CryptoRng;
+
+pub struct Signer {
+    key: SigningKey,
+}
+impl Signer {
+    /// Key the signer with the key returned by `Proof::finish`.
+    pub fn new(key: &[u8; 512]) -> Signer {
+        Signer { key: SigningKey::new(key) }
+    }
+    /// Sign a request with a fresh nonce. `now` is the current time in seconds
+    /// since the Unix epoch, the server rejects it if the clocks are too far apart.
+    pub fn sign<R: CryptoRng + ?Sized>(&self, rng: &mut R, method: &str, path: &str, body: &[u8], now: u64) -> Signature {
+        let mut nonce = [0; 16];
+        rng.fill_bytes(&mut nonce);
+        self.key.sign(method, path, body, now, nonce)
+    }
+}
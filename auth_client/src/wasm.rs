@@ -0,0 +1,549 @@
+//! JS bindings, enabled by the `wasm` feature.
+extern crate wee_alloc;
+
+// Use `wee_alloc` as the global allocator.
+#[global_allocator]
+static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+
+use alloc::{string::String, vec::Vec};
+use core::mem::MaybeUninit;
+
+use auth_common::{ClientProving, ClientStart, ClientStarting, Data, ErrorResp, PreAuthResp};
+use js_sys::{Array, ArrayBuffer, Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::{ChannelError, ClientError, Login, Proof, Signature, Suite, saved::StateError};
+
+#[wasm_bindgen]
+unsafe extern "C" {
+    pub type Crypto;
+    #[wasm_bindgen(method)]
+    fn getRandomValues(this: &Crypto, buffer: Uint8Array) -> ArrayBuffer;
+
+    /// The `signal` of an `AbortController`.
+    pub type AbortSignal;
+    #[wasm_bindgen(method, getter)]
+    fn aborted(this: &AbortSignal) -> bool;
+    #[wasm_bindgen(method, getter)]
+    fn reason(this: &AbortSignal) -> JsValue;
+    #[wasm_bindgen(method, js_name = addEventListener)]
+    fn add_event_listener(this: &AbortSignal, kind: &str, listener: &Function);
+
+    /// A Web Worker that runs `serveWorker`, see `Step1.authInWorker`.
+    pub type Worker;
+    #[wasm_bindgen(method, js_name = postMessage)]
+    fn post_message(this: &Worker, msg: &JsValue);
+    #[wasm_bindgen(method, setter)]
+    fn set_onmessage(this: &Worker, handler: &Function);
+    #[wasm_bindgen(method, setter)]
+    fn set_onerror(this: &Worker, handler: &Function);
+    #[wasm_bindgen(method)]
+    fn terminate(this: &Worker);
+
+    // global in workers, where `serveWorker` runs
+    #[wasm_bindgen(js_name = postMessage)]
+    fn post_reply(msg: &JsValue);
+
+    // global in windows, workers and Node
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(callback: &Function, ms: i32) -> JsValue;
+}
+
+/// Let the event loop run before continuing.
+async fn yield_now() {
+    let promise = Promise::new(&mut |resolve, _| {
+        set_timeout(&resolve, 0);
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+/// Progress reporting and cancellation of an async call.
+struct Task {
+    progress: Option<Function>,
+    signal: Option<AbortSignal>,
+}
+impl Task {
+    /// Report `done` of `total` steps. Unless that was the last one, yield,
+    /// then fail if the call was aborted.
+    async fn step(&self, done: usize, total: usize) -> Result<(), JsValue> {
+        if let Some(progress) = &self.progress {
+            progress.call2(&JsValue::NULL, &(done as u32).into(), &(total as u32).into())?;
+        }
+        if done == total {
+            return Ok(());
+        }
+        yield_now().await;
+        self.check()
+    }
+    /// Fail if the call was aborted.
+    fn check(&self) -> Result<(), JsValue> {
+        match &self.signal {
+            Some(signal) if signal.aborted() => Err(signal.reason()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn rand_buf<const N: usize>(crypto: &Crypto) -> Result<[u8; N], JsValue> {
+    let bytes = unsafe {
+        let mut buf = MaybeUninit::<[u8; N]>::uninit();
+        let filled = crypto.getRandomValues(Uint8Array::view_mut_raw(buf.as_mut_ptr().cast(), N));
+        let len = filled.byte_length();
+        if len as usize != N {
+            return Err(JsValue::from_str("not enough data"));
+        }
+        buf.assume_init()
+    };
+    Ok(bytes)
+}
+
+/// `crypto.getRandomValues` as a `CryptoRng`. Panics if it fails, use
+/// `rand_buf` where the error can be reported instead.
+struct JsRng<'a>(&'a Crypto);
+impl rand_core::RngCore for JsRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        let filled = unsafe { self.0.getRandomValues(Uint8Array::view_mut_raw(dst.as_mut_ptr(), dst.len())) };
+        assert_eq!(filled.byte_length() as usize, dst.len(), "not enough data");
+    }
+}
+impl rand_core::CryptoRng for JsRng<'_> {}
+
+impl From<ClientError> for JsValue {
+    fn from(e: ClientError) -> JsValue {
+        match e {
+            ClientError::Rejected(resp) => Failure { resp }.into(),
+            ClientError::Frame(_) => JsValue::from_str("invalid data"),
+            ClientError::Handshake(_) => JsValue::from_str("authentication failed"),
+            ClientError::Encode => JsValue::from_str("username too long"),
+        }
+    }
+}
+
+impl From<StateError> for JsValue {
+    fn from(e: StateError) -> JsValue {
+        JsValue::from_str(match e {
+            StateError::Malformed | StateError::WrongKind => "invalid state",
+            StateError::Expired => "state expired",
+            StateError::Invalid => "wrong key or modified state",
+        })
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    (js_sys::Date::now() / 1000.) as u64
+}
+
+fn state_key(key: &[u8]) -> Result<&[u8; 32], JsValue> {
+    key.try_into().map_err(|_| JsValue::from_str("key must be 32 bytes"))
+}
+
+/// Seconds the state sent to a worker stays valid.
+const WORKER_TTL: u32 = 60;
+
+#[wasm_bindgen]
+pub struct Step1 {
+    login: Login,
+}
+
+#[wasm_bindgen]
+impl Step1 {
+    /// Draws `a` and computes `A`, which blocks for a moment. `create` does
+    /// the same without blocking.
+    #[wasm_bindgen(constructor)]
+    pub fn new(crypto: &Crypto) -> Result<Step1, JsValue> {
+        Ok(Step1 { login: Login::from_secret(rand_buf(crypto)?) })
+    }
+    /// `new` without blocking the event loop: `A` is computed in steps, with
+    /// the event loop running in between. Call it early, e.g. when the login
+    /// form is shown, so the login does not wait for `A` once the user submits.
+    ///
+    /// `progress` and `signal` work as in `authAsync`.
+    pub async fn create(crypto: Crypto, progress: Option<Function>, signal: Option<AbortSignal>) -> Result<Step1, JsValue> {
+        let task = Task { progress, signal };
+        let mut starting = ClientStart::steps(rand_buf(&crypto)?);
+        task.step(0, ClientStarting::STEPS).await?;
+        while starting.steps_left() > 0 {
+            starting.step();
+            task.step(ClientStarting::STEPS - starting.steps_left(), ClientStarting::STEPS).await?;
+        }
+        Ok(Step1 { login: Login { start: starting.finish() } })
+    }
+    pub fn req(&self, username: &str) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(&self.login.request(username)?[..]))
+    }
+    /// Encrypt the state under the 32 byte `key`, to resume the login with
+    /// `import` within `ttl` seconds, e.g. after a page navigation.
+    pub fn export(&self, crypto: &Crypto, key: &[u8], ttl: u32) -> Result<Uint8Array, JsValue> {
+        let blob = self.login.export(&mut JsRng(crypto), state_key(key)?, now() + ttl as u64);
+        Ok(Uint8Array::from(&blob[..]))
+    }
+    pub fn import(blob: &[u8], key: &[u8]) -> Result<Step1, JsValue> {
+        Ok(Step1 { login: Login::import(blob, state_key(key)?, now())? })
+    }
+    pub fn auth(
+        self,
+        username: &str,
+        password: &str,
+        resp: &[u8],
+    ) -> Result<Step2, JsValue> {
+        let proof = self.login.process(resp, username, password)?;
+        Ok(Step2 { proof })
+    }
+    /// `auth` in steps, with the event loop running in between.
+    ///
+    /// The password hasher is one step and blocks for all of its run, which
+    /// is most of the login. The exponentiation after it is split into
+    /// `ClientProving::STEPS` small steps. For a UI that does not freeze at
+    /// all, use `authInWorker`.
+    ///
+    /// `progress` is called with the number of finished steps and the total.
+    /// Once `signal` is aborted, the promise rejects with `signal.reason`.
+    #[wasm_bindgen(js_name = authAsync)]
+    pub async fn auth_async(
+        self,
+        username: String,
+        password: String,
+        resp: Vec<u8>,
+        progress: Option<Function>,
+        signal: Option<AbortSignal>,
+    ) -> Result<Step2, JsValue> {
+        let task = Task { progress, signal };
+        let total = 1 + ClientProving::STEPS;
+        let challenge = self.login.receive(&resp)?;
+        task.step(0, total).await?;
+        let mut proving = challenge.hash_password(&username, &password).hashed.proof_steps().map_err(ClientError::from)?;
+        task.step(1, total).await?;
+        while proving.steps_left() > 0 {
+            proving.step();
+            task.step(total - proving.steps_left(), total).await?;
+        }
+        Ok(Step2 { proof: Proof { proof: proving.finish() } })
+    }
+    /// `auth` in a Web Worker, so the UI does not freeze at all.
+    ///
+    /// The worker loads this module and calls `serveWorker`. It gets the state
+    /// as an `export` blob together with its key, and sends back the blob of
+    /// the `Step2`. One login at a time per worker.
+    ///
+    /// Once `signal` is aborted, the worker is terminated and the promise
+    /// rejects with `signal.reason`. Start a new worker for the next login.
+    #[wasm_bindgen(js_name = authInWorker)]
+    pub async fn auth_in_worker(
+        self,
+        worker: Worker,
+        crypto: Crypto,
+        username: String,
+        password: String,
+        resp: Vec<u8>,
+        signal: Option<AbortSignal>,
+    ) -> Result<Step2, JsValue> {
+        let task = Task { progress: None, signal };
+        // an `ErrorResp` is thrown as a `Failure` here, the worker could only send a string
+        crate::decode::<PreAuthResp>(&resp)?;
+        task.check()?;
+        let key: [u8; 32] = rand_buf(&crypto)?;
+        let blob = self.export(&crypto, &key, WORKER_TTL)?;
+        let msg = Array::of5(
+            &blob,
+            &Uint8Array::from(&key[..]),
+            &username.into(),
+            &password.into(),
+            &Uint8Array::from(&resp[..]),
+        );
+        let reply = Promise::new(&mut |resolve, reject| {
+            worker.set_onmessage(&resolve);
+            worker.set_onerror(&reject);
+            if let Some(signal) = &task.signal {
+                signal.add_event_listener("abort", &reject);
+            }
+            worker.post_message(&msg);
+        });
+        let event = match JsFuture::from(reply).await {
+            Ok(event) => event,
+            Err(e) => {
+                if let Err(reason) = task.check() {
+                    worker.terminate();
+                    return Err(reason);
+                }
+                return Err(e);
+            }
+        };
+        match Reflect::get(&event, &"data".into())?.dyn_into::<Uint8Array>() {
+            Ok(blob) => Step2::import(&blob.to_vec(), &key),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Answer the logins of `Step1.authInWorker`. Call it in the worker once the
+/// module is loaded, it installs the `onmessage` handler of the worker.
+#[wasm_bindgen(js_name = serveWorker)]
+pub fn serve_worker() -> Result<(), JsValue> {
+    let handler = Closure::<dyn FnMut(JsValue)>::new(|event: JsValue| {
+        let reply = Reflect::get(&event, &"data".into()).and_then(|msg| worker_auth(&msg));
+        match reply {
+            Ok(blob) => post_reply(&blob),
+            Err(e) => post_reply(&e),
+        }
+    });
+    Reflect::set(&js_sys::global(), &"onmessage".into(), handler.as_ref())?;
+    handler.forget();
+    Ok(())
+}
+
+/// Run the login of one message from `Step1.authInWorker` and return the
+/// reply, for a worker that handles its messages itself.
+#[wasm_bindgen(js_name = workerAuth)]
+pub fn worker_auth(msg: &JsValue) -> Result<Uint8Array, JsValue> {
+    let invalid = || JsValue::from_str("invalid message");
+    let msg: &Array = msg.dyn_ref().ok_or_else(invalid)?;
+    let bytes = |i| msg.get(i).dyn_into::<Uint8Array>().map(|a| a.to_vec()).map_err(|_| invalid());
+    let string = |i| msg.get(i).as_string().ok_or_else(invalid);
+    let key = bytes(1)?;
+    let step1 = Step1::import(&bytes(0)?, &key)?;
+    let step2 = step1.auth(&string(2)?, &string(3)?, &bytes(4)?)?;
+    let crypto: Crypto = Reflect::get(&js_sys::global(), &"crypto".into())?.unchecked_into();
+    step2.export(&crypto, &key, WORKER_TTL)
+}
+
+/// Build a `RegisterReq`. `suite` defaults to `Suite::Blake2bArgon2d`.
+#[wasm_bindgen]
+pub fn register(crypto: &Crypto, username: &str, password: &str, suite: Option<u8>) -> Result<Uint8Array, JsValue> {
+    let suite = match suite {
+        Some(tag) => Suite::from_u8(tag).ok_or(JsValue::from_str("unknown suite"))?,
+        None => Suite::default(),
+    };
+    let req = crate::register(&mut JsRng(crypto), username, password, suite)?;
+    Ok(Uint8Array::from(&req[..]))
+}
+
+/// Decoded `ErrorResp` sent by the server when a request fails.
+///
+/// Methods expecting a server reply throw this when they get an `ErrorResp` instead.
+#[wasm_bindgen]
+pub struct Failure {
+    resp: ErrorResp,
+}
+
+#[wasm_bindgen]
+impl Failure {
+    #[wasm_bindgen(constructor)]
+    pub fn new(resp: &[u8]) -> Result<Failure, JsValue> {
+        let resp = crate::decode_error(resp)?;
+        Ok(Failure { resp })
+    }
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> u8 {
+        self.resp.code as u8
+    }
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> JsValue {
+        JsValue::from_str(self.resp.code.message())
+    }
+    /// Seconds to wait before trying again, if the server sent a hint.
+    #[wasm_bindgen(getter, js_name = retryAfter)]
+    pub fn retry_after(&self) -> Option<u32> {
+        self.resp.retry_after
+    }
+}
+
+#[wasm_bindgen]
+pub struct Step2 {
+    proof: Proof,
+}
+
+#[wasm_bindgen]
+impl Step2 {
+    pub fn req(&self) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(&self.proof.request()?[..]))
+    }
+    /// See `Step1.export`.
+    pub fn export(&self, crypto: &Crypto, key: &[u8], ttl: u32) -> Result<Uint8Array, JsValue> {
+        let blob = self.proof.export(&mut JsRng(crypto), state_key(key)?, now() + ttl as u64);
+        Ok(Uint8Array::from(&blob[..]))
+    }
+    pub fn import(blob: &[u8], key: &[u8]) -> Result<Step2, JsValue> {
+        Ok(Step2 { proof: Proof::import(blob, state_key(key)?, now())? })
+    }
+    /// The number of digits if `resp` asks for a TOTP code, see `totpReq`.
+    #[wasm_bindgen(js_name = totpChallenge)]
+    pub fn totp_challenge(&self, resp: &[u8]) -> Result<Option<u8>, JsValue> {
+        Ok(self.proof.totp_challenge(resp)?)
+    }
+    /// Answer the challenge in `resp` with the code from the authenticator
+    /// app. The server replies with the response for `finish`.
+    #[wasm_bindgen(js_name = totpReq)]
+    pub fn totp_req(&self, resp: &[u8], code: u32) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(&self.proof.totp_request(resp, code)?[..]))
+    }
+    /// The shared key, without checking the proof of the server. Prefer `finish`.
+    pub fn get_key(&self) -> Uint8Array {
+        Uint8Array::from(&self.proof.unverified_key()[..])
+    }
+    /// Check the `AuthResponse` of the server and return the shared key.
+    pub fn finish(self, resp: &[u8]) -> Result<Uint8Array, JsValue> {
+        let key = self.proof.finish(resp)?;
+        Ok(Uint8Array::from(&key[..]))
+    }
+}
+
+/// A ticket to log in again without the password, from the key of
+/// `Step2.finish` and the `Ticket` the server sent after the login.
+#[wasm_bindgen]
+pub struct Resumption {
+    inner: crate::Resumption,
+}
+
+#[wasm_bindgen]
+impl Resumption {
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &[u8], ticket: &[u8]) -> Result<Resumption, JsValue> {
+        let key = key.try_into().map_err(|_| JsValue::from_str("key must be 512 bytes"))?;
+        Ok(Resumption { inner: crate::Resumption::new(key, ticket)? })
+    }
+    /// Seconds since the Unix epoch.
+    #[wasm_bindgen(getter)]
+    pub fn expires(&self) -> f64 {
+        self.inner.expires() as f64
+    }
+    /// Encrypt the ticket under the 32 byte `key`, to keep it between visits.
+    pub fn export(&self, crypto: &Crypto, key: &[u8]) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(&self.inner.export(&mut JsRng(crypto), state_key(key)?)[..]))
+    }
+    pub fn import(blob: &[u8], key: &[u8]) -> Result<Resumption, JsValue> {
+        Ok(Resumption { inner: crate::Resumption::import(blob, state_key(key)?, now())? })
+    }
+    /// Uses up the ticket, the next one comes with `Resumed`.
+    pub fn start(self, crypto: &Crypto) -> Resuming {
+        Resuming { inner: self.inner.start(&mut JsRng(crypto)) }
+    }
+}
+
+#[wasm_bindgen]
+pub struct Resuming {
+    inner: crate::Resuming,
+}
+
+#[wasm_bindgen]
+impl Resuming {
+    pub fn req(&self) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(&self.inner.request()?[..]))
+    }
+    /// Check the `ResumeResp` of the server.
+    pub fn finish(self, resp: &[u8]) -> Result<Resumed, JsValue> {
+        let (key, next) = self.inner.finish(resp)?;
+        Ok(Resumed { key, next: Some(next) })
+    }
+}
+
+#[wasm_bindgen]
+pub struct Resumed {
+    key: [u8; 512],
+    next: Option<crate::Resumption>,
+}
+
+#[wasm_bindgen]
+impl Resumed {
+    /// The new shared key, for `Channel` and `Signer`.
+    #[wasm_bindgen(getter)]
+    pub fn key(&self) -> Uint8Array {
+        Uint8Array::from(&self.key[..])
+    }
+    /// The `Resumption` for next time. Only returned once.
+    pub fn resumption(&mut self) -> Option<Resumption> {
+        self.next.take().map(|inner| Resumption { inner })
+    }
+}
+
+/// Key of a device the server trusts, from the key of `Step2.finish` of the
+/// login it was trusted in.
+#[wasm_bindgen]
+pub struct TrustedDevice {
+    inner: crate::TrustedDevice,
+}
+
+#[wasm_bindgen]
+impl TrustedDevice {
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &[u8]) -> Result<TrustedDevice, JsValue> {
+        let key = key.try_into().map_err(|_| JsValue::from_str("key must be 512 bytes"))?;
+        Ok(TrustedDevice { inner: crate::TrustedDevice::new(key) })
+    }
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> Uint8Array {
+        Uint8Array::from(&self.inner.id()[..])
+    }
+    /// The `DeviceProof` for a later login, `key` is from that login. The
+    /// application sends it its own way, no login message carries it.
+    pub fn proof(&self, key: &[u8]) -> Result<Uint8Array, JsValue> {
+        let key = key.try_into().map_err(|_| JsValue::from_str("key must be 512 bytes"))?;
+        Ok(Uint8Array::from(&self.inner.proof(key)?[..]))
+    }
+    /// Encrypt the device key under the 32 byte `key`, for `ttl` seconds.
+    pub fn export(&self, crypto: &Crypto, key: &[u8], ttl: u32) -> Result<Uint8Array, JsValue> {
+        let blob = self.inner.export(&mut JsRng(crypto), state_key(key)?, now() + ttl as u64);
+        Ok(Uint8Array::from(&blob[..]))
+    }
+    pub fn import(blob: &[u8], key: &[u8]) -> Result<TrustedDevice, JsValue> {
+        Ok(TrustedDevice { inner: crate::TrustedDevice::import(blob, state_key(key)?, now())? })
+    }
+}
+
+fn channel_error(e: ChannelError) -> JsValue {
+    JsValue::from_str(match e {
+        ChannelError::Malformed | ChannelError::Invalid | ChannelError::BufferTooSmall => "invalid record",
+        ChannelError::Replayed => "replayed record",
+        ChannelError::Exhausted => "channel exhausted",
+    })
+}
+
+/// Encrypted channel to the server, keyed with the key from `Step2.finish`.
+#[wasm_bindgen]
+pub struct Channel {
+    inner: crate::Channel,
+}
+
+#[wasm_bindgen]
+impl Channel {
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &[u8]) -> Result<Channel, JsValue> {
+        let key = key.try_into().map_err(|_| JsValue::from_str("key must be 512 bytes"))?;
+        Ok(Channel { inner: crate::Channel::new(key, crate::Role::Client) })
+    }
+    pub fn seal(&mut self, msg: &[u8]) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(&self.inner.seal_to_vec(msg).map_err(channel_error)?[..]))
+    }
+    pub fn open(&mut self, record: &[u8]) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(&self.inner.open_to_vec(record).map_err(channel_error)?[..]))
+    }
+}
+
+/// Signs requests to the server, keyed with the key from `Step2.finish`.
+#[wasm_bindgen]
+pub struct Signer {
+    inner: crate::Signer,
+}
+
+#[wasm_bindgen]
+impl Signer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &[u8]) -> Result<Signer, JsValue> {
+        let key = key.try_into().map_err(|_| JsValue::from_str("key must be 512 bytes"))?;
+        Ok(Signer { inner: crate::Signer::new(key) })
+    }
+    /// The encoded signature of the request, stamped with the current time.
+    /// Send it along, e.g. base64 encoded in a header.
+    pub fn sign(&self, crypto: &Crypto, method: &str, path: &str, body: &[u8]) -> Uint8Array {
+        let sig = self.inner.sign(&mut JsRng(crypto), method, path, body, now());
+        let mut buf = [0; Signature::SIZE];
+        Uint8Array::from(sig.encode(&mut buf).unwrap())
+    }
+}
@@ -0,0 +1,165 @@
+//! Run with `wasm-pack test --node -- --features wasm`.
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use std::{cell::RefCell, rc::Rc};
+
+use auth::wasm::{AbortSignal, Crypto, Step1, Step2, Worker, worker_auth};
+use auth_common::{
+    AuthReq, ClientProving, ClientStarting, PreAuthReq, ServerChallenge, Suite, decode_message,
+    encode_message,
+};
+use js_sys::{Function, Object, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, prelude::*};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen]
+unsafe extern "C" {
+    type AbortController;
+    #[wasm_bindgen(constructor)]
+    fn new() -> AbortController;
+    #[wasm_bindgen(method, getter)]
+    fn signal(this: &AbortController) -> AbortSignal;
+    #[wasm_bindgen(method)]
+    fn abort(this: &AbortController);
+}
+
+fn crypto() -> Crypto {
+    Reflect::get(&js_sys::global(), &"crypto".into()).unwrap().unchecked_into()
+}
+
+/// Start a login and let the server answer the `PreAuthReq`.
+fn start(salt: [u8; 32], verifier: &[u8; 512]) -> (Step1, ServerChallenge, Vec<u8>) {
+    let step1 = Step1::new(&crypto()).unwrap();
+    let req = step1.req("user").unwrap().to_vec();
+    let req: PreAuthReq = decode_message(&req).unwrap();
+    let server = ServerChallenge::new(&req, salt, verifier, Suite::default(), [3; 512], [4; 8]).unwrap();
+    let mut buf = [0; 1024];
+    let resp = encode_message(&server.message(), &mut buf).unwrap().to_vec();
+    (step1, server, resp)
+}
+
+/// The `(done, total)` of every progress call.
+type Steps = Rc<RefCell<Vec<(usize, usize)>>>;
+
+/// A progress callback and the calls it got.
+fn progress() -> (Function, Steps) {
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    let progress = Closure::<dyn FnMut(u32, u32)>::new({
+        let steps = steps.clone();
+        move |done, total| steps.borrow_mut().push((done as usize, total as usize))
+    });
+    let callback = progress.as_ref().clone().unchecked_into();
+    progress.forget();
+    (callback, steps)
+}
+
+/// Stands in for a `Worker` running `serveWorker`, answers right away.
+fn worker() -> Worker {
+    let worker = Object::new();
+    let post = Closure::<dyn FnMut(JsValue)>::new({
+        let worker = worker.clone();
+        move |msg: JsValue| {
+            let event = Object::new();
+            let reply = worker_auth(&msg).map_or_else(|e| e, JsValue::from);
+            Reflect::set(&event, &"data".into(), &reply).unwrap();
+            let onmessage: Function = Reflect::get(&worker, &"onmessage".into()).unwrap().unchecked_into();
+            onmessage.call1(&JsValue::NULL, &event).unwrap();
+        }
+    });
+    Reflect::set(&worker, &"postMessage".into(), post.as_ref()).unwrap();
+    post.forget();
+    worker.unchecked_into()
+}
+
+/// Check that the login ends with the key of the server.
+fn finish(step2: Step2, server: ServerChallenge) {
+    let req = step2.req().unwrap().to_vec();
+    let server = server.process(&decode_message::<AuthReq>(&req).unwrap()).unwrap();
+    let mut buf = [0; 1024];
+    let resp = encode_message(&server.message(), &mut buf).unwrap();
+    let key: Uint8Array = step2.finish(resp).unwrap();
+    assert_eq!(key.to_vec(), server.key());
+}
+
+#[wasm_bindgen_test]
+async fn test_create() {
+    let (callback, steps) = progress();
+    let step1 = Step1::create(crypto(), Some(callback), None).await.unwrap();
+    let expected: Vec<_> = (0..=ClientStarting::STEPS).map(|done| (done, ClientStarting::STEPS)).collect();
+    assert_eq!(*steps.borrow(), expected);
+
+    let salt = [1; 32];
+    let verifier = Suite::default().compute_verifier(b"user", b"password", &salt);
+    let req = step1.req("user").unwrap().to_vec();
+    let server = ServerChallenge::new(&decode_message(&req).unwrap(), salt, &verifier, Suite::default(), [3; 512], [4; 8]).unwrap();
+    let mut buf = [0; 1024];
+    let resp = encode_message(&server.message(), &mut buf).unwrap();
+    finish(step1.auth("user", "password", resp).unwrap(), server);
+}
+
+#[wasm_bindgen_test]
+async fn test_auth_async() {
+    let salt = [1; 32];
+    let verifier = Suite::default().compute_verifier(b"user", b"password", &salt);
+    let (step1, server, resp) = start(salt, &verifier);
+
+    let (callback, steps) = progress();
+    let step2 = step1
+        .auth_async("user".into(), "password".into(), resp, Some(callback), None)
+        .await
+        .unwrap();
+    let total = 1 + ClientProving::STEPS;
+    let expected: Vec<_> = (0..=total).map(|done| (done, total)).collect();
+    assert_eq!(*steps.borrow(), expected);
+    finish(step2, server);
+}
+
+#[wasm_bindgen_test]
+async fn test_auth_in_worker() {
+    let salt = [1; 32];
+    let verifier = Suite::default().compute_verifier(b"user", b"password", &salt);
+    let (step1, server, resp) = start(salt, &verifier);
+
+    let step2 = step1
+        .auth_in_worker(worker(), crypto(), "user".into(), "password".into(), resp, None)
+        .await
+        .unwrap();
+    finish(step2, server);
+
+    // the worker reports failures as a string
+    let err = worker_auth(&JsValue::from_str("login")).err().unwrap();
+    assert_eq!(err, "invalid message");
+}
+
+#[wasm_bindgen_test]
+async fn test_auth_abort() {
+    let salt = [1; 32];
+    let verifier = Suite::default().compute_verifier(b"user", b"password", &salt);
+    let (step1, _, resp) = start(salt, &verifier);
+
+    let controller = AbortController::new();
+    controller.abort();
+    let err = step1
+        .auth_async("user".into(), "password".into(), resp, None, Some(controller.signal()))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(Reflect::get(&err, &"name".into()).unwrap(), "AbortError");
+}
+
+#[wasm_bindgen_test]
+fn test_export() {
+    let salt = [1; 32];
+    let verifier = Suite::default().compute_verifier(b"user", b"password", &salt);
+    let (step1, _, resp) = start(salt, &verifier);
+
+    let key = [5; 32];
+    let blob = step1.export(&crypto(), &key, 60).unwrap().to_vec();
+    assert!(Step1::import(&blob, &[6; 32]).is_err());
+    let step1 = Step1::import(&blob, &key).unwrap();
+    let step2 = step1.auth("user", "password", &resp).unwrap();
+    let blob = step2.export(&crypto(), &key, 60).unwrap().to_vec();
+    assert!(Step1::import(&blob, &key).is_err());
+    let step2 = Step2::import(&blob, &key).unwrap();
+    assert!(step2.req().is_ok());
+}
@@ -0,0 +1,183 @@
+//! Encrypted channel keyed from the shared key of a login.
+//!
+//! Each direction has its own ChaCha20-Poly1305 key, derived from the shared
+//! key with HKDF-BLAKE2b. A sealed record is
+//!
+//! | seq (u64 LE) | ciphertext | tag (16) |
+//!
+//! `seq` counts the records sent in that direction and is the nonce. The
+//! receiver accepts each `seq` once, records may arrive out of order as long
+//! as they are no more than `WINDOW` behind the newest one.
+use blake2::Blake2b512;
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag};
+use hkdf::SimpleHkdf;
+
+/// Bytes a sealed record adds to the message.
+pub const OVERHEAD: usize = 8 + 16;
+/// How far behind the newest record an older one may arrive.
+pub const WINDOW: u64 = 64;
+
+/// The side of the login this end of the channel was on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelError {
+    /// Shorter than `OVERHEAD`.
+    Malformed,
+    /// The tag does not match: wrong key, wrong direction or a modified record.
+    Invalid,
+    /// The record was opened before, or is too old to tell.
+    Replayed,
+    /// All sequence numbers are used up. Log in again.
+    Exhausted,
+    /// The output buffer is too small.
+    BufferTooSmall,
+}
+
+pub struct Channel {
+    send: ChaCha20Poly1305,
+    recv: ChaCha20Poly1305,
+    next_seq: u64,
+    /// Highest `seq` opened so far, plus one. Zero before the first record.
+    recv_top: u64,
+    /// Bit `i` is set if `recv_top - 1 - i` was opened.
+    recv_seen: u64,
+}
+impl Channel {
+    pub fn new(shared_key: &[u8; 512], role: Role) -> Channel {
+        let hkdf = SimpleHkdf::<Blake2b512>::new(None, shared_key);
+        let derive = |info: &[u8]| {
+            let mut key = [0; 32];
+            hkdf.expand(info, &mut key).expect("32 bytes is a valid length");
+            ChaCha20Poly1305::new(&key.into())
+        };
+        let to_server = derive(b"auth channel client to server");
+        let to_client = derive(b"auth channel server to client");
+        let (send, recv) = match role {
+            Role::Client => (to_server, to_client),
+            Role::Server => (to_client, to_server),
+        };
+        Channel { send, recv, next_seq: 0, recv_top: 0, recv_seen: 0 }
+    }
+
+    /// Encrypt `msg` into `out`, which needs `msg.len() + OVERHEAD` bytes.
+    pub fn seal<'b>(&mut self, msg: &[u8], out: &'b mut [u8]) -> Result<&'b [u8], ChannelError> {
+        let out = out.get_mut(..msg.len() + OVERHEAD).ok_or(ChannelError::BufferTooSmall)?;
+        let seq = self.next_seq;
+        self.next_seq = seq.checked_add(1).ok_or(ChannelError::Exhausted)?;
+
+        let (header, rest) = out.split_at_mut(8);
+        let (body, tag) = rest.split_at_mut(msg.len());
+        header.copy_from_slice(&seq.to_le_bytes());
+        body.copy_from_slice(msg);
+        let t = self
+            .send
+            .encrypt_in_place_detached(&nonce(seq), header, body)
+            .expect("record is short enough");
+        tag.copy_from_slice(&t);
+        Ok(out)
+    }
+
+    /// Decrypt a record sealed by the other side into `out`, which needs
+    /// `record.len() - OVERHEAD` bytes.
+    pub fn open<'b>(&mut self, record: &[u8], out: &'b mut [u8]) -> Result<&'b [u8], ChannelError> {
+        if record.len() < OVERHEAD {
+            return Err(ChannelError::Malformed);
+        }
+        let (header, rest) = record.split_at(8);
+        let (body, tag) = rest.split_at(rest.len() - 16);
+        let seq = u64::from_le_bytes(header.try_into().unwrap());
+        if !self.fresh(seq) {
+            return Err(ChannelError::Replayed);
+        }
+        let out = out.get_mut(..body.len()).ok_or(ChannelError::BufferTooSmall)?;
+        out.copy_from_slice(body);
+        if self.recv.decrypt_in_place_detached(&nonce(seq), header, out, Tag::from_slice(tag)).is_err() {
+            out.fill(0);
+            return Err(ChannelError::Invalid);
+        }
+        self.mark(seq);
+        Ok(out)
+    }
+
+    /// `seal` into a new `Vec`.
+    #[cfg(feature = "alloc")]
+    pub fn seal_to_vec(&mut self, msg: &[u8]) -> Result<alloc::vec::Vec<u8>, ChannelError> {
+        let mut out = alloc::vec![0; msg.len() + OVERHEAD];
+        self.seal(msg, &mut out)?;
+        Ok(out)
+    }
+    /// `open` into a new `Vec`.
+    #[cfg(feature = "alloc")]
+    pub fn open_to_vec(&mut self, record: &[u8]) -> Result<alloc::vec::Vec<u8>, ChannelError> {
+        let mut out = alloc::vec![0; record.len().saturating_sub(OVERHEAD)];
+        self.open(record, &mut out)?;
+        Ok(out)
+    }
+
+    /// `seq` was not opened yet and is within the window.
+    fn fresh(&self, seq: u64) -> bool {
+        if seq >= self.recv_top {
+            return true;
+        }
+        let age = self.recv_top - 1 - seq;
+        age < WINDOW && self.recv_seen & (1 << age) == 0
+    }
+    fn mark(&mut self, seq: u64) {
+        if seq >= self.recv_top {
+            let shift = seq + 1 - self.recv_top;
+            self.recv_seen = if shift >= WINDOW { 0 } else { self.recv_seen << shift };
+            self.recv_seen |= 1;
+            self.recv_top = seq + 1;
+        } else {
+            self.recv_seen |= 1 << (self.recv_top - 1 - seq);
+        }
+    }
+}
+
+fn nonce(seq: u64) -> Nonce {
+    let mut nonce = Nonce::default();
+    nonce[..8].copy_from_slice(&seq.to_le_bytes());
+    nonce
+}
+
+#[test]
+fn test_channel() {
+    let key = [7; 512];
+    let mut client = Channel::new(&key, Role::Client);
+    let mut server = Channel::new(&key, Role::Server);
+    let (mut sealed, mut opened) = ([0; 64], [0; 64]);
+
+    let records: [[u8; 5 + OVERHEAD]; 3] = core::array::from_fn(|i| {
+        let mut out = [0; 5 + OVERHEAD];
+        client.seal(&[i as u8; 5], &mut out).unwrap();
+        out
+    });
+    // out of order is fine, twice is not
+    assert_eq!(server.open(&records[2], &mut opened).unwrap(), [2; 5]);
+    assert_eq!(server.open(&records[0], &mut opened).unwrap(), [0; 5]);
+    assert_eq!(server.open(&records[0], &mut opened), Err(ChannelError::Replayed));
+    assert_eq!(server.open(&records[1], &mut opened).unwrap(), [1; 5]);
+
+    // each direction has its own key
+    let mut reply = [0; 5 + OVERHEAD];
+    reply.copy_from_slice(server.seal(b"hello", &mut sealed).unwrap());
+    assert_eq!(Channel::new(&key, Role::Server).open(&reply, &mut opened), Err(ChannelError::Invalid));
+    assert_eq!(client.open(&reply, &mut opened).unwrap(), b"hello");
+
+    let mut modified = records[1];
+    modified[9] ^= 1;
+    assert_eq!(Channel::new(&key, Role::Server).open(&modified, &mut opened), Err(ChannelError::Invalid));
+
+    // a record that fell out of the window
+    let mut server = Channel::new(&key, Role::Server);
+    for _ in 0..WINDOW {
+        let record = client.seal(&[], &mut sealed).unwrap();
+        server.open(record, &mut opened).unwrap();
+    }
+    assert_eq!(server.open(&records[0], &mut opened), Err(ChannelError::Replayed));
+}
@@ -0,0 +1,71 @@
+//! Keys of trusted devices.
+//!
+//! When a user trusts a device after a login, both sides derive a device id
+//! and key from the shared key of that login with HKDF-BLAKE2b. The server
+//! stores them with the user, the client on the device. After a later login,
+//! the client sends a `DeviceProof`: the id and a MAC with the device key over
+//! the shared key of the new login, so the proof can not be replayed in
+//! another login.
+use blake2::{Blake2b512, Digest};
+use hkdf::SimpleHkdf;
+use hmac::{Mac, SimpleHmac};
+use subtle::ConstantTimeEq;
+
+use crate::DeviceProof;
+
+pub struct DeviceKey {
+    id: [u8; 16],
+    key: [u8; 32],
+}
+impl DeviceKey {
+    /// The key of the device trusted in the login with `shared_key`.
+    pub fn new(shared_key: &[u8; 512]) -> DeviceKey {
+        let mut okm = [0; 48];
+        SimpleHkdf::<Blake2b512>::new(None, shared_key)
+            .expand(b"auth device key", &mut okm)
+            .expect("48 bytes is a valid length");
+        let (id, key) = okm.split_at(16);
+        DeviceKey { id: id.try_into().unwrap(), key: key.try_into().unwrap() }
+    }
+    pub fn from_parts(id: [u8; 16], key: [u8; 32]) -> DeviceKey {
+        DeviceKey { id, key }
+    }
+    pub fn id(&self) -> [u8; 16] {
+        self.id
+    }
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Proof for the login with `shared_key`.
+    pub fn proof(&self, shared_key: &[u8; 512]) -> DeviceProof {
+        DeviceProof { id: self.id, proof: self.mac(shared_key) }
+    }
+    /// Check a proof for the login with `shared_key`, including the id.
+    pub fn verify(&self, shared_key: &[u8; 512], proof: &DeviceProof) -> bool {
+        let mac = self.mac(shared_key);
+        (self.id.ct_eq(&proof.id) & mac.ct_eq(&proof.proof)).into()
+    }
+    fn mac(&self, shared_key: &[u8; 512]) -> [u8; 32] {
+        let mut mac = SimpleHmac::<Blake2b512>::new_from_slice(&self.key).expect("any key length works");
+        mac.update(b"auth device proof");
+        mac.update(&self.id);
+        mac.update(&Blake2b512::digest(shared_key));
+        let mut out = [0; 32];
+        out.copy_from_slice(&mac.finalize().into_bytes()[..32]);
+        out
+    }
+}
+
+#[test]
+fn test_device_key() {
+    let device = DeviceKey::new(&[7; 512]);
+    let server = DeviceKey::from_parts(device.id(), *device.key());
+    let proof = device.proof(&[1; 512]);
+    assert!(server.verify(&[1; 512], &proof));
+    // bound to the login it was made for
+    assert!(!server.verify(&[2; 512], &proof));
+    assert!(!DeviceKey::new(&[8; 512]).verify(&[1; 512], &proof));
+    assert!(!server.verify(&[1; 512], &DeviceProof { id: [0; 16], ..proof }));
+    assert_ne!(DeviceKey::new(&[8; 512]).id(), device.id());
+}
@@ -0,0 +1,469 @@
+//! Transport-free login handshake.
+//!
+//! Each state consumes the message it expects and returns the next state,
+//! which holds the message to send. The client goes from `ClientStart` to
+//! `ClientProof` to the shared key, the server from `ServerChallenge` to
+//! `ServerDone`. Random secrets are passed in, this module has no RNG.
+//!
+//! `ClientStart::process` can also be run in steps, through `ClientChallenge`
+//! and `ClientHashed`, to yield between the expensive parts. The 4096-bit
+//! exponentiations can be split further: `ClientStart::steps` computes `A`
+//! and `ClientHashed::proof_steps` the shared key a few bytes of the exponent
+//! at a time. The password hasher runs in one piece.
+//!
+//! `to_parts` and `from_parts` of `ClientStart` and `ClientProof` give the raw
+//! state, secrets included, so a client can store it between the messages.
+//! Never store it unencrypted, `auth::saved` seals it.
+use blake2::{Blake2b512, Blake2s256, Digest, digest::Output};
+use srp::{
+    A2, A2id, DigestNum, Encoding, SrpAuthError, U256, U4096, Uint,
+    client::{PowSteps, SrpClient4096, SrpClientVerifier4096},
+    groups::g_4096::G,
+    server::SrpServer4096,
+};
+use subtle::ConstantTimeEq;
+
+use crate::{AuthReq, AuthResponse, ErrorCode, PreAuthReq, PreAuthResp, Suite, Suites};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// The client offers no suite this side implements.
+    UnsupportedSuite,
+    /// The peer sent a public value that is zero modulo N.
+    IllegalParameter,
+    /// The proof of the peer is wrong.
+    BadProof,
+}
+impl HandshakeError {
+    /// The code to send back to the peer.
+    pub fn code(self) -> ErrorCode {
+        match self {
+            HandshakeError::UnsupportedSuite => ErrorCode::UnsupportedSuite,
+            HandshakeError::IllegalParameter | HandshakeError::BadProof => ErrorCode::AuthFailed,
+        }
+    }
+}
+impl From<SrpAuthError> for HandshakeError {
+    fn from(e: SrpAuthError) -> Self {
+        match e {
+            SrpAuthError::IllegalParameter => HandshakeError::IllegalParameter,
+            SrpAuthError::BadRecordMac => HandshakeError::BadProof,
+        }
+    }
+}
+
+/// Copy a proof into the zero padded field of `AuthReq` or `AuthResponse`.
+fn pad_proof(proof: &[u8]) -> [u8; 64] {
+    let mut out = [0; 64];
+    out[..proof.len()].copy_from_slice(proof);
+    out
+}
+
+/// Client that sent its `PreAuthReq`. `A` is computed once, up front.
+pub struct ClientStart {
+    a: [u8; 512],
+    a_pub: [u8; 512],
+}
+impl ClientStart {
+    /// `secret` has to come from a cryptographic RNG.
+    pub fn new(secret: [u8; 512]) -> Self {
+        let a = U4096::from_le_bytes(secret);
+        // A = g^a does not depend on the suite
+        let a_pub = SrpClient4096::<A2, Blake2b512>::new(a).compute_a_pub().to_le_bytes();
+        ClientStart { a: secret, a_pub }
+    }
+    /// `new` in steps, see `ClientStarting`.
+    pub fn steps(secret: [u8; 512]) -> ClientStarting {
+        let a = U4096::from_le_bytes(secret);
+        ClientStarting { secret, a_pub: PowSteps::new(&G, &a.to_be_bytes()) }
+    }
+    /// The secret `a` and `A`. See the module docs before storing them.
+    pub fn to_parts(&self) -> ([u8; 512], [u8; 512]) {
+        (self.a, self.a_pub)
+    }
+    /// Restore the state from `to_parts`.
+    pub fn from_parts(secret: [u8; 512], a_pub: [u8; 512]) -> Self {
+        ClientStart { a: secret, a_pub }
+    }
+    pub fn message<'a>(&'a self, username: &'a str) -> PreAuthReq<'a> {
+        PreAuthReq { a_pub: &self.a_pub, username, suites: Suites::all() }
+    }
+    /// Derive the proof from the password. This runs the password hasher of
+    /// the suite the server picked and is the slow part of the login.
+    ///
+    /// Same as `receive`, `hash_password` and `compute_proof` in a row.
+    pub fn process(self, resp: &PreAuthResp, username: &str, password: &str) -> Result<ClientProof, HandshakeError> {
+        self.receive(resp).hash_password(username, password).compute_proof()
+    }
+    /// Take the `PreAuthResp`, before doing any of the expensive work.
+    pub fn receive(self, resp: &PreAuthResp) -> ClientChallenge {
+        ClientChallenge {
+            a: U4096::from_le_bytes(self.a),
+            a_pub: U4096::from_le_bytes(self.a_pub),
+            salt: resp.salt,
+            b_pub: U4096::from_le_bytes(*resp.b_pub),
+            key: resp.key,
+            suite: resp.suite,
+        }
+    }
+}
+
+/// `ClientStart` computing `A`. Call `step` until `steps_left` is zero, or
+/// `finish` to run the rest at once.
+pub struct ClientStarting {
+    secret: [u8; 512],
+    a_pub: PowSteps,
+}
+impl ClientStarting {
+    /// The steps of a new `ClientStarting`.
+    pub const STEPS: usize = U4096::BYTES.div_ceil(PowSteps::STEP_BYTES);
+
+    pub fn steps_left(&self) -> usize {
+        self.a_pub.steps_left()
+    }
+    pub fn step(&mut self) {
+        self.a_pub.step();
+    }
+    pub fn finish(self) -> ClientStart {
+        ClientStart { a: self.secret, a_pub: self.a_pub.finish().to_le_bytes() }
+    }
+}
+
+/// Client that received the `PreAuthResp`.
+pub struct ClientChallenge {
+    a: U4096,
+    a_pub: U4096,
+    salt: [u8; 32],
+    b_pub: U4096,
+    key: [u8; 8],
+    suite: Suite,
+}
+impl ClientChallenge {
+    /// Run the password hasher of the suite and compute `g^x`.
+    pub fn hash_password(self, username: &str, password: &str) -> ClientHashed {
+        type Blake2b = SrpClient4096<A2, Blake2b512>;
+        type Blake2s = SrpClient4096<A2id, Blake2s256>;
+        let (username, password, salt) = (username.as_bytes(), password.as_bytes(), &self.salt);
+        let (x, g_x) = match self.suite {
+            Suite::Blake2bArgon2d => {
+                let x = Blake2b::compute_x(username, password, salt);
+                (x, Blake2b::compute_v(&x))
+            }
+            Suite::Blake2sArgon2id => {
+                let x = Blake2s::compute_x(username, password, salt);
+                (x, Blake2s::compute_v(&x))
+            }
+        };
+        ClientHashed {
+            a: self.a,
+            a_pub: self.a_pub,
+            x,
+            g_x,
+            b_pub: self.b_pub,
+            key: self.key,
+            suite: self.suite,
+        }
+    }
+}
+
+/// Client that derived its private key from the password.
+pub struct ClientHashed {
+    a: U4096,
+    a_pub: U4096,
+    x: U256,
+    g_x: U4096,
+    b_pub: U4096,
+    key: [u8; 8],
+    suite: Suite,
+}
+impl ClientHashed {
+    /// Compute the shared key and the proof.
+    pub fn compute_proof(self) -> Result<ClientProof, HandshakeError> {
+        let Self { a, a_pub, x, g_x, b_pub, key, suite } = self;
+        Ok(match suite {
+            Suite::Blake2bArgon2d => ClientProof::new(
+                SrpClient4096::<A2, Blake2b512>::new(a).process_reply_precomputed(&a_pub, &x, &g_x, &b_pub)?,
+                key,
+                suite,
+            ),
+            Suite::Blake2sArgon2id => ClientProof::new(
+                SrpClient4096::<A2id, Blake2s256>::new(a).process_reply_precomputed(&a_pub, &x, &g_x, &b_pub)?,
+                key,
+                suite,
+            ),
+        })
+    }
+    /// `compute_proof` in steps, see `ClientProving`.
+    pub fn proof_steps(self) -> Result<ClientProving, HandshakeError> {
+        let Self { a, a_pub, x, g_x, b_pub, key, suite } = self;
+        let premaster = match suite {
+            Suite::Blake2bArgon2d => SrpClient4096::<A2, Blake2b512>::new(a).premaster_steps(&a_pub, &x, &g_x, &b_pub)?,
+            Suite::Blake2sArgon2id => SrpClient4096::<A2id, Blake2s256>::new(a).premaster_steps(&a_pub, &x, &g_x, &b_pub)?,
+        };
+        Ok(ClientProving { premaster, a_pub, b_pub, key, suite })
+    }
+}
+
+/// `ClientHashed` computing the shared key. Call `step` until `steps_left`
+/// is zero, or `finish` to run the rest at once.
+pub struct ClientProving {
+    premaster: PowSteps,
+    a_pub: U4096,
+    b_pub: U4096,
+    key: [u8; 8],
+    suite: Suite,
+}
+impl ClientProving {
+    /// The steps of a new `ClientProving`.
+    pub const STEPS: usize = U4096::BYTES.div_ceil(PowSteps::STEP_BYTES);
+
+    pub fn steps_left(&self) -> usize {
+        self.premaster.steps_left()
+    }
+    pub fn step(&mut self) {
+        self.premaster.step();
+    }
+    pub fn finish(self) -> ClientProof {
+        let Self { premaster, a_pub, b_pub, key, suite } = self;
+        let premaster = premaster.finish();
+        match suite {
+            Suite::Blake2bArgon2d => ClientProof::new(SrpClient4096::<A2, Blake2b512>::verifier(&a_pub, &b_pub, &premaster), key, suite),
+            Suite::Blake2sArgon2id => ClientProof::new(SrpClient4096::<A2id, Blake2s256>::verifier(&a_pub, &b_pub, &premaster), key, suite),
+        }
+    }
+}
+
+/// Client that sent its `AuthReq` and waits for the proof of the server.
+pub struct ClientProof {
+    suite: Suite,
+    proof: [u8; 64],
+    /// The proof the server has to send
+    server_proof: [u8; 64],
+    shared_key: [u8; 512],
+    key: [u8; 8],
+}
+/// The raw state of a `ClientProof`. See the module docs before storing it.
+pub struct ClientProofParts {
+    pub suite: Suite,
+    pub proof: [u8; 64],
+    pub server_proof: [u8; 64],
+    pub shared_key: [u8; 512],
+    pub key: [u8; 8],
+}
+impl ClientProof {
+    pub fn to_parts(&self) -> ClientProofParts {
+        let ClientProof { suite, proof, server_proof, shared_key, key } = *self;
+        ClientProofParts { suite, proof, server_proof, shared_key, key }
+    }
+    /// Restore the state from `to_parts`.
+    pub fn from_parts(parts: ClientProofParts) -> Self {
+        let ClientProofParts { suite, proof, server_proof, shared_key, key } = parts;
+        ClientProof { suite, proof, server_proof, shared_key, key }
+    }
+    fn new<D: Digest>(verifier: SrpClientVerifier4096<D>, key: [u8; 8], suite: Suite) -> Self {
+        ClientProof {
+            suite,
+            proof: pad_proof(verifier.proof()),
+            server_proof: pad_proof(verifier.server_proof()),
+            shared_key: verifier.key().to_le_bytes(),
+            key,
+        }
+    }
+    pub fn message(&self) -> AuthReq {
+        AuthReq { proof: self.proof, key: self.key }
+    }
+    /// The shared key before the server proved that it knows the verifier.
+    pub fn unverified_key(&self) -> [u8; 512] {
+        self.shared_key
+    }
+    /// Check the proof of the server and return the shared key. The padding
+    /// after a shorter proof has to be zero.
+    pub fn finish(self, resp: &AuthResponse) -> Result<[u8; 512], HandshakeError> {
+        if self.server_proof.ct_eq(&resp.proof).unwrap_u8() != 1 {
+            return Err(HandshakeError::BadProof);
+        }
+        Ok(self.shared_key)
+    }
+}
+
+/// SRP server for the digest of the suite the user was registered with.
+enum SuiteServer {
+    Blake2b512(SrpServer4096<Blake2b512>),
+    Blake2s256(SrpServer4096<Blake2s256>),
+}
+
+/// Server that answered a `PreAuthReq` and waits for the `AuthReq`.
+pub struct ServerChallenge {
+    server: SuiteServer,
+    salt: [u8; 32],
+    v: U4096,
+    a_pub: U4096,
+    b_pub: [u8; 512],
+    key: [u8; 8],
+    suite: Suite,
+}
+impl ServerChallenge {
+    /// Answer `req` for the user registered with `salt`, `verifier` and `suite`.
+    ///
+    /// If the client does not offer `suite`, the challenge uses a suite it
+    /// offers and the proof fails in `process`, like a wrong password. A
+    /// rejection here would tell which users exist and their suites, since
+    /// decoys of unknown users are always accepted.
+    ///
+    /// `secret` has to come from a cryptographic RNG. `key` is echoed by the
+    /// client in its `AuthReq` so the server can find this state again.
+    pub fn new(
+        req: &PreAuthReq,
+        salt: [u8; 32],
+        verifier: &[u8; 512],
+        suite: Suite,
+        secret: [u8; 512],
+        key: [u8; 8],
+    ) -> Result<Self, HandshakeError> {
+        let suite = match req.suites.contains(suite) {
+            true => suite,
+            false => req.suites.iter().next().ok_or(HandshakeError::UnsupportedSuite)?,
+        };
+        let b = U4096::from_le_bytes(secret);
+        let v = U4096::from_le_bytes(*verifier);
+        let (server, b_pub) = match suite {
+            Suite::Blake2bArgon2d => {
+                let server = SrpServer4096::new(b);
+                let b_pub = server.compute_public_ephemeral(&v);
+                (SuiteServer::Blake2b512(server), b_pub)
+            }
+            Suite::Blake2sArgon2id => {
+                let server = SrpServer4096::new(b);
+                let b_pub = server.compute_public_ephemeral(&v);
+                (SuiteServer::Blake2s256(server), b_pub)
+            }
+        };
+        Ok(ServerChallenge {
+            server,
+            salt,
+            v,
+            a_pub: U4096::from_le_bytes(*req.a_pub),
+            b_pub: b_pub.to_le_bytes(),
+            key,
+            suite,
+        })
+    }
+    pub fn message(&self) -> PreAuthResp<'_> {
+        PreAuthResp { salt: self.salt, b_pub: &self.b_pub, key: self.key, suite: self.suite }
+    }
+    pub fn suite(&self) -> Suite {
+        self.suite
+    }
+    /// Check the proof of the client. The padding after a shorter proof has
+    /// to be zero.
+    pub fn process(self, req: &AuthReq) -> Result<ServerDone, HandshakeError> {
+        let (key, proof) = match &self.server {
+            SuiteServer::Blake2b512(server) => verify(server, &self.v, &self.a_pub, &req.proof)?,
+            SuiteServer::Blake2s256(server) => verify(server, &self.v, &self.a_pub, &req.proof)?,
+        };
+        Ok(ServerDone { key, proof })
+    }
+}
+fn verify<const D_N: usize, D: Digest>(
+    server: &SrpServer4096<D>,
+    v: &U4096,
+    a_pub: &U4096,
+    proof: &[u8; 64],
+) -> Result<(U4096, [u8; 64]), SrpAuthError>
+where
+    Output<D>: DigestNum<Num = Uint<D_N>>,
+{
+    let (proof, padding) = proof.split_at(<D as Digest>::output_size());
+    if padding.iter().any(|&b| b != 0) {
+        return Err(SrpAuthError::BadRecordMac);
+    }
+    let verifier = server.process_reply(v, a_pub)?;
+    verifier.verify_client(proof)?;
+    Ok((*verifier.key(), pad_proof(verifier.proof())))
+}
+
+/// Server that accepted the proof of the client.
+pub struct ServerDone {
+    key: U4096,
+    proof: [u8; 64],
+}
+impl ServerDone {
+    /// The proof of the server, for the client to check.
+    pub fn message(&self) -> AuthResponse {
+        AuthResponse { proof: self.proof }
+    }
+    pub fn key(&self) -> [u8; 512] {
+        self.key.to_le_bytes()
+    }
+}
+
+#[test]
+fn test_handshake() {
+    let suite = Suite::Blake2bArgon2d;
+    let salt = [1; 32];
+    let verifier = suite.compute_verifier(b"user", b"password", &salt);
+
+    for (password, ok) in [("password", true), ("wrong", false)] {
+        let client = ClientStart::new([2; 512]);
+        let server = ServerChallenge::new(&client.message("user"), salt, &verifier, suite, [3; 512], [4; 8]).unwrap();
+        // both client states survive a round trip through their parts
+        let (secret, a_pub) = client.to_parts();
+        let client = ClientStart::from_parts(secret, a_pub);
+        let client = client.process(&server.message(), "user", password).unwrap();
+        let client = ClientProof::from_parts(client.to_parts());
+        match server.process(&client.message()) {
+            Ok(server) => {
+                assert!(ok);
+                assert_eq!(client.finish(&server.message()).unwrap(), server.key());
+            }
+            Err(e) => {
+                assert!(!ok);
+                assert_eq!(e, HandshakeError::BadProof);
+                assert_eq!(client.finish(&AuthResponse { proof: [0; 64] }).err(), Some(HandshakeError::BadProof));
+            }
+        }
+    }
+
+    // the padding after a 32-byte proof is part of the proof
+    let suite = Suite::Blake2sArgon2id;
+    let verifier = suite.compute_verifier(b"user", b"password", &salt);
+    let client = ClientStart::new([2; 512]);
+    let server = ServerChallenge::new(&client.message("user"), salt, &verifier, suite, [3; 512], [4; 8]).unwrap();
+    let client = client.process(&server.message(), "user", "password").unwrap();
+    let mut req = client.message();
+    req.proof[40] = 1;
+    let retry = ServerChallenge::new(&ClientStart::new([2; 512]).message("user"), salt, &verifier, suite, [3; 512], [4; 8]).unwrap();
+    assert_eq!(retry.process(&req).err(), Some(HandshakeError::BadProof));
+    let mut resp = server.process(&client.message()).unwrap().message();
+    resp.proof[40] = 1;
+    assert_eq!(client.finish(&resp).err(), Some(HandshakeError::BadProof));
+
+    let suite = Suite::Blake2bArgon2d;
+    let verifier = suite.compute_verifier(b"user", b"password", &salt);
+    // the stepped client ends up with the same proof and key
+    let mut starting = ClientStart::steps([2; 512]);
+    assert_eq!(starting.steps_left(), ClientStarting::STEPS);
+    starting.step();
+    assert_eq!(starting.steps_left(), ClientStarting::STEPS - 1);
+    let client = starting.finish();
+    assert_eq!(client.to_parts(), ClientStart::new([2; 512]).to_parts());
+    let server = ServerChallenge::new(&client.message("user"), salt, &verifier, suite, [3; 512], [4; 8]).unwrap();
+    let mut proving = client.receive(&server.message()).hash_password("user", "password").proof_steps().unwrap();
+    assert_eq!(proving.steps_left(), ClientProving::STEPS);
+    proving.step();
+    let client = proving.finish();
+    let server = server.process(&client.message()).unwrap();
+    assert_eq!(client.finish(&server.message()).unwrap(), server.key());
+
+    let client = ClientStart::new([2; 512]);
+    let req = PreAuthReq { suites: Suites::NONE, ..client.message("user") };
+    let err = ServerChallenge::new(&req, salt, &verifier, suite, [3; 512], [4; 8]).err();
+    assert_eq!(err, Some(HandshakeError::UnsupportedSuite));
+
+    // a suite the client does not offer fails like a wrong password
+    let req = PreAuthReq { suites: Suites::NONE.with(Suite::Blake2sArgon2id), ..client.message("user") };
+    let server = ServerChallenge::new(&req, salt, &verifier, suite, [3; 512], [4; 8]).unwrap();
+    assert_eq!(server.suite(), Suite::Blake2sArgon2id);
+    let client = client.process(&server.message(), "user", "password").unwrap();
+    assert_eq!(server.process(&client.message()).err(), Some(HandshakeError::BadProof));
+}
@@ -0,0 +1,201 @@
+//! JSON form of the messages, enabled by the `serde` feature.
+//!
+//! Byte fields are standard base64 strings, like in the records of
+//! `auth_server`. `Suite` and `ErrorCode` are their numeric tags and `Suites`
+//! is the bitset as a number, so the form does not change when variants get
+//! renamed.
+//!
+//! Messages that borrow from the binary input only implement `Serialize`.
+//! The owned versions in this module serialize to the same JSON and can be
+//! deserialized, `as_message` turns them back into the borrowed message.
+use alloc::{string::String, vec::Vec};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{Bytes, ErrorCode, Suite, Suites};
+
+/// Serialize a borrowed byte array as base64.
+pub(crate) fn array_ref<const N: usize, S: Serializer>(
+    value: &&[u8; N],
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    serdapt_base64::StdBase64Array::serialize(*value, s)
+}
+
+/// Serialize a length-prefixed byte field as base64.
+pub(crate) fn bytes<const MAX: usize, S: Serializer>(value: &Bytes<MAX>, s: S) -> Result<S::Ok, S::Error> {
+    serdapt_base64::StdBase64::serialize(&value.0, s)
+}
+
+impl From<Suite> for u8 {
+    fn from(suite: Suite) -> u8 {
+        suite as u8
+    }
+}
+impl TryFrom<u8> for Suite {
+    type Error = &'static str;
+    fn try_from(tag: u8) -> Result<Suite, Self::Error> {
+        Suite::from_u8(tag).ok_or("unknown suite")
+    }
+}
+
+impl From<ErrorCode> for u8 {
+    fn from(code: ErrorCode) -> u8 {
+        code as u8
+    }
+}
+impl TryFrom<u8> for ErrorCode {
+    type Error = &'static str;
+    fn try_from(code: u8) -> Result<ErrorCode, Self::Error> {
+        ErrorCode::from_u8(code).ok_or("unknown error code")
+    }
+}
+
+/// Owned `crate::PreAuthReq`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreAuthReq {
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub a_pub: [u8; 512],
+    pub username: String,
+    pub suites: Suites,
+}
+impl PreAuthReq {
+    pub fn as_message(&self) -> crate::PreAuthReq<'_> {
+        crate::PreAuthReq { a_pub: &self.a_pub, username: &self.username, suites: self.suites }
+    }
+}
+impl From<crate::PreAuthReq<'_>> for PreAuthReq {
+    fn from(msg: crate::PreAuthReq) -> Self {
+        PreAuthReq { a_pub: *msg.a_pub, username: msg.username.into(), suites: msg.suites }
+    }
+}
+
+/// Owned `crate::PreAuthResp`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreAuthResp {
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub salt: [u8; 32],
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub b_pub: [u8; 512],
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub key: [u8; 8],
+    pub suite: Suite,
+}
+impl PreAuthResp {
+    pub fn as_message(&self) -> crate::PreAuthResp<'_> {
+        crate::PreAuthResp { salt: self.salt, b_pub: &self.b_pub, key: self.key, suite: self.suite }
+    }
+}
+impl From<crate::PreAuthResp<'_>> for PreAuthResp {
+    fn from(msg: crate::PreAuthResp) -> Self {
+        PreAuthResp { salt: msg.salt, b_pub: *msg.b_pub, key: msg.key, suite: msg.suite }
+    }
+}
+
+/// Owned `crate::RegisterReq`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegisterReq {
+    pub username: String,
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub salt: [u8; 32],
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub verifier: [u8; 512],
+    pub suite: Suite,
+}
+impl RegisterReq {
+    pub fn as_message(&self) -> crate::RegisterReq<'_> {
+        crate::RegisterReq {
+            username: &self.username,
+            salt: self.salt,
+            verifier: &self.verifier,
+            suite: self.suite,
+        }
+    }
+}
+impl From<crate::RegisterReq<'_>> for RegisterReq {
+    fn from(msg: crate::RegisterReq) -> Self {
+        RegisterReq {
+            username: msg.username.into(),
+            salt: msg.salt,
+            verifier: *msg.verifier,
+            suite: msg.suite,
+        }
+    }
+}
+
+/// Owned `crate::Ticket`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ticket {
+    #[serde(with = "serdapt_base64::StdBase64")]
+    pub ticket: Vec<u8>,
+    pub expires: u64,
+}
+impl Ticket {
+    pub fn as_message(&self) -> crate::Ticket<'_> {
+        crate::Ticket { ticket: Bytes(&self.ticket), expires: self.expires }
+    }
+}
+impl From<crate::Ticket<'_>> for Ticket {
+    fn from(msg: crate::Ticket) -> Self {
+        Ticket { ticket: msg.ticket.0.into(), expires: msg.expires }
+    }
+}
+
+/// Owned `crate::ResumeReq`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResumeReq {
+    #[serde(with = "serdapt_base64::StdBase64")]
+    pub ticket: Vec<u8>,
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub share: [u8; 512],
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub proof: [u8; 32],
+}
+impl ResumeReq {
+    pub fn as_message(&self) -> crate::ResumeReq<'_> {
+        crate::ResumeReq { ticket: Bytes(&self.ticket), share: &self.share, proof: self.proof }
+    }
+}
+impl From<crate::ResumeReq<'_>> for ResumeReq {
+    fn from(msg: crate::ResumeReq) -> Self {
+        ResumeReq { ticket: msg.ticket.0.into(), share: *msg.share, proof: msg.proof }
+    }
+}
+
+/// Owned `crate::ResumeResp`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResumeResp {
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub share: [u8; 512],
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    pub proof: [u8; 32],
+    #[serde(with = "serdapt_base64::StdBase64")]
+    pub ticket: Vec<u8>,
+    pub expires: u64,
+}
+impl ResumeResp {
+    pub fn as_message(&self) -> crate::ResumeResp<'_> {
+        crate::ResumeResp { share: &self.share, proof: self.proof, ticket: Bytes(&self.ticket), expires: self.expires }
+    }
+}
+impl From<crate::ResumeResp<'_>> for ResumeResp {
+    fn from(msg: crate::ResumeResp) -> Self {
+        ResumeResp { share: *msg.share, proof: msg.proof, ticket: msg.ticket.0.into(), expires: msg.expires }
+    }
+}
+
+/// Owned `crate::RecoverReq`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecoverReq {
+    pub code: String,
+    pub register: RegisterReq,
+}
+impl RecoverReq {
+    pub fn as_message(&self) -> crate::RecoverReq<'_> {
+        crate::RecoverReq { code: &self.code, register: self.register.as_message() }
+    }
+}
+impl From<crate::RecoverReq<'_>> for RecoverReq {
+    fn from(msg: crate::RecoverReq) -> Self {
+        RecoverReq { code: msg.code.into(), register: msg.register.into() }
+    }
+}
@@ -1,66 +1,450 @@
 #![no_std]
 
+// lets `#[derive(Data)]` refer to `::auth_common` inside this crate
+extern crate self as auth_common;
+
+pub use auth_derive::Data;
+
+mod suite;
+pub use suite::{Suite, Suites};
+
+mod handshake;
+pub use handshake::{
+    ClientChallenge, ClientHashed, ClientProof, ClientProofParts, ClientProving, ClientStart,
+    ClientStarting, HandshakeError, ServerChallenge, ServerDone,
+};
+
+pub mod channel;
+pub mod device;
+pub mod recovery;
+pub mod resume;
+pub mod signing;
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "serde")]
+pub mod json;
+
 macro_rules! microserde {
-    ($(pub struct $name:ident $(<$lt:lifetime>)? { $( pub $field:ident: $typ:ty, )*} )*) => {
-        $(
-            pub struct $name $(<$lt>)? {
-                $( pub $field: $typ,)*
+    () => {};
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident $(<$lt:lifetime>)? { $( $(#[$fmeta:meta])* pub $field:ident: $typ:ty, )*}
+        $($rest:tt)*
+    ) => {
+        $(#[$meta])*
+        pub struct $name $(<$lt>)? {
+            $( $(#[$fmeta])* pub $field: $typ,)*
+        }
+        impl<'a> Data<'a> for $name $(<$lt>)? {
+            const SIZE: usize = 0 $( + <$typ as Data<'a>>::SIZE )*;
+
+            fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+                $(
+                    let buf = self.$field.write(buf)?;
+                )*
+                Some(buf)
             }
-            impl<'a> Data<'a> for $name $(<$lt>)? {
-                const SIZE: usize = 0 $( + <$typ as Data<'a>>::SIZE )*;
+            fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+                $(
+                    let ($field, data) = <$typ>::decode(data)?;
+                )*
+                Some(($name {
+                    $( $field, )*
+                }, data))
+            }
+        }
+        microserde!($($rest)*);
+    };
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident $(<$lt:lifetime>)? { $( $(#[$vmeta:meta])* $variant:ident $(($payload:ty))? = $tag:literal, )*}
+        $($rest:tt)*
+    ) => {
+        $(#[$meta])*
+        pub enum $name $(<$lt>)? {
+            $( $(#[$vmeta])* $variant $(($payload))?, )*
+        }
+        impl<'a> Data<'a> for $name $(<$lt>)? {
+            const SIZE: usize = 1 + max_size(&[$( microserde!(@size $($payload)?), )*]);
 
-                fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
-                    $(
-                        let buf = self.$field.write(buf)?;
-                    )*
-                    Some(buf)
-                }
-                fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
-                    $(
-                        let ($field, data) = <$typ>::decode(data)?;
-                    )*
-                    Some(($name {
-                        $( $field, )*
-                    }, data))
-                }
+            fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+                $(
+                    microserde!(@write self, buf, $name, $variant, $tag $(, $payload)?);
+                )*
+                #[allow(unreachable_code)]
+                None
             }
-        )*
+            fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+                let (tag, data) = u8::decode(data)?;
+                $(
+                    if tag == $tag {
+                        return microserde!(@decode data, $name, $variant $(, $payload)?);
+                    }
+                )*
+                None
+            }
+        }
+        microserde!($($rest)*);
     };
+    (@size) => { 0 };
+    (@size $payload:ty) => { <$payload as Data<'a>>::SIZE };
+    (@write $self:ident, $buf:ident, $name:ident, $variant:ident, $tag:literal) => {
+        if let $name::$variant = $self {
+            return ($tag as u8).write($buf);
+        }
+    };
+    (@write $self:ident, $buf:ident, $name:ident, $variant:ident, $tag:literal, $payload:ty) => {
+        if let $name::$variant(value) = $self {
+            let buf = ($tag as u8).write($buf)?;
+            return value.write(buf);
+        }
+    };
+    (@decode $data:ident, $name:ident, $variant:ident) => { Some(($name::$variant, $data)) };
+    (@decode $data:ident, $name:ident, $variant:ident, $payload:ty) => {{
+        let (value, data) = <$payload>::decode($data)?;
+        Some(($name::$variant(value), data))
+    }};
+}
+
+// size of enums declared with `microserde!`
+#[allow(dead_code)]
+const fn max_size(sizes: &[usize]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < sizes.len() {
+        if sizes[i] > max {
+            max = sizes[i];
+        }
+        i += 1;
+    }
+    max
 }
 
+// Messages borrowing from the input only implement `Serialize`,
+// `json` has owned versions of them that can be deserialized.
 microserde! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
     pub struct PreAuthReq<'a> {
-        pub a_pub: [u8; 512],
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::array_ref"))]
+        pub a_pub: &'a [u8; 512],
         pub username: &'a str,
+        pub suites: Suites,
     }
 
-    pub struct PreAuthResp {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct PreAuthResp<'a> {
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
         pub salt: [u8; 32],
-        pub b_pub: [u8; 512],
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::array_ref"))]
+        pub b_pub: &'a [u8; 512],
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
         pub key: [u8; 8],
+        pub suite: Suite,
     }
 
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct AuthReq {
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
         pub proof: [u8; 64],
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
         pub key: [u8; 8],
     }
 
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct AuthResponse {
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
         pub proof: [u8; 64],
     }
 
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
     pub struct RegisterReq<'a> {
         pub username: &'a str,
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
         pub salt: [u8; 32],
-        pub verifier: [u8; 512],
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::array_ref"))]
+        pub verifier: &'a [u8; 512],
+        pub suite: Suite,
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ErrorResp {
+        pub code: ErrorCode,
+        /// Seconds the client should wait before retrying.
+        pub retry_after: Option<u32>,
+    }
+    /// Sent after a login, to resume it later with a `ResumeReq`.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct Ticket<'a> {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::bytes"))]
+        pub ticket: Bytes<'a, { resume::MAX_TICKET_LEN }>,
+        /// Seconds since the Unix epoch. The server refuses the ticket from then on.
+        pub expires: u64,
+    }
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct ResumeReq<'a> {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::bytes"))]
+        pub ticket: Bytes<'a, { resume::MAX_TICKET_LEN }>,
+        /// See `resume::DhShare`.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::array_ref"))]
+        pub share: &'a [u8; 512],
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
+        pub proof: [u8; 32],
+    }
+    /// Carries the next ticket, the one in the `ResumeReq` is used up.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct ResumeResp<'a> {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::array_ref"))]
+        pub share: &'a [u8; 512],
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
+        pub proof: [u8; 32],
+        #[cfg_attr(feature = "serde", serde(serialize_with = "json::bytes"))]
+        pub ticket: Bytes<'a, { resume::MAX_TICKET_LEN }>,
+        pub expires: u64,
+    }
+    /// Proves a login comes from a trusted device, see `device`. No login
+    /// message carries it, the application sends it its own way.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct DeviceProof {
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
+        pub id: [u8; 16],
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
+        pub proof: [u8; 32],
+    }
+    /// Sent in place of the `AuthResponse` when the user has a second factor.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct TotpChallenge {
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
+        pub key: [u8; 8],
+        /// Length of the code.
+        pub digits: u8,
+    }
+    /// The code from the authenticator app. Answered with the `AuthResponse`.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct TotpReq {
+        #[cfg_attr(feature = "serde", serde(with = "serdapt_base64::StdBase64Array"))]
+        pub key: [u8; 8],
+        pub code: u32,
+    }
+    /// Set a new password with a recovery code, for the user of `register`.
+    /// Not encrypted, send it over TLS only.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct RecoverReq<'a> {
+        pub code: &'a str,
+        pub register: RegisterReq<'a>,
+    }
+}
+
+/// Reason sent to the client when a request fails.
+///
+/// The codes are deliberately coarse: an unknown user and a wrong password
+/// both end up as `AuthFailed`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Data)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "u8", try_from = "u8"))]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The request could not be decoded.
+    Malformed = 1,
+    /// The handshake is unknown or has expired. Start again with a `PreAuthReq`.
+    HandshakeExpired = 2,
+    /// Wrong username or password.
+    AuthFailed = 3,
+    /// Too many attempts. See `ErrorResp::retry_after`.
+    TooManyAttempts = 4,
+    /// Something went wrong on the server.
+    Internal = 5,
+    /// The message used a protocol version the server does not speak.
+    UnsupportedVersion = 6,
+    /// The client offered no suite the server implements.
+    UnsupportedSuite = 7,
+    /// A user with this name is registered already.
+    UsernameTaken = 8,
+    /// The operation needs a recent password proof. Log in again within the session.
+    StepUpRequired = 9,
+    /// The ticket is expired, used or unknown. Log in with the password.
+    ResumeFailed = 10,
+}
+impl ErrorCode {
+    pub fn from_u8(code: u8) -> Option<Self> {
+        Some(match code {
+            1 => ErrorCode::Malformed,
+            2 => ErrorCode::HandshakeExpired,
+            3 => ErrorCode::AuthFailed,
+            4 => ErrorCode::TooManyAttempts,
+            5 => ErrorCode::Internal,
+            6 => ErrorCode::UnsupportedVersion,
+            7 => ErrorCode::UnsupportedSuite,
+            8 => ErrorCode::UsernameTaken,
+            9 => ErrorCode::StepUpRequired,
+            10 => ErrorCode::ResumeFailed,
+            _ => return None,
+        })
+    }
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorCode::Malformed => "malformed request",
+            ErrorCode::HandshakeExpired => "login expired",
+            ErrorCode::AuthFailed => "authentication failed",
+            ErrorCode::TooManyAttempts => "too many attempts",
+            ErrorCode::Internal => "internal error",
+            ErrorCode::UnsupportedVersion => "unsupported protocol version",
+            ErrorCode::UnsupportedSuite => "unsupported algorithm suite",
+            ErrorCode::UsernameTaken => "username is taken",
+            ErrorCode::StepUpRequired => "password confirmation required",
+            ErrorCode::ResumeFailed => "resumption failed",
+        }
+    }
+}
+
+impl ErrorResp {
+    pub fn new(code: ErrorCode) -> Self {
+        ErrorResp { code, retry_after: None }
+    }
+    /// Ask the client to wait `secs` seconds before trying again.
+    pub fn with_retry_after(self, secs: u32) -> Self {
+        ErrorResp { retry_after: Some(secs), ..self }
+    }
+}
+
+macro_rules! message_types {
+    ($($name:ident $(<$lt:lifetime>)? = $tag:literal,)*) => {
+        /// Tag in the frame header identifying the message that follows.
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum MessageType {
+            $( $name = $tag, )*
+        }
+        impl MessageType {
+            pub fn from_u8(tag: u8) -> Option<Self> {
+                match tag {
+                    $( $tag => Some(MessageType::$name), )*
+                    _ => None,
+                }
+            }
+        }
+        $(
+            impl<'a> Message<'a> for $name $(<$lt>)? {
+                const TYPE: MessageType = MessageType::$name;
+            }
+        )*
+    };
+}
+
+message_types! {
+    PreAuthReq<'a> = 1,
+    PreAuthResp<'a> = 2,
+    AuthReq = 3,
+    AuthResponse = 4,
+    RegisterReq<'a> = 5,
+    ErrorResp = 6,
+    Ticket<'a> = 7,
+    ResumeReq<'a> = 8,
+    ResumeResp<'a> = 9,
+    DeviceProof = 10,
+    TotpChallenge = 11,
+    TotpReq = 12,
+    RecoverReq<'a> = 13,
+}
+
+/// Version written into every frame header. Decoders reject anything else.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Size of the frame header: version, message type and a `u16` payload length.
+pub const HEADER_SIZE: usize = 4;
+
+/// A message that can be sent on the wire inside a frame.
+pub trait Message<'a>: Data<'a> {
+    const TYPE: MessageType;
+    /// Upper bound of the framed message, header included.
+    const FRAME_SIZE: usize = HEADER_SIZE + Self::SIZE;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// Not enough data for the header or the payload.
+    Truncated,
+    /// The frame was written with a protocol version we do not understand.
+    UnsupportedVersion(u8),
+    /// The message type tag is unknown.
+    UnknownType(u8),
+    /// A valid message, but not the one that was expected.
+    UnexpectedType(MessageType),
+    /// The payload does not decode as the message in the header.
+    Malformed,
+}
+impl FrameError {
+    /// The code to send back to the peer.
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            FrameError::UnsupportedVersion(_) => ErrorCode::UnsupportedVersion,
+            _ => ErrorCode::Malformed,
+        }
+    }
+}
+
+/// A decoded frame header and the payload it covers.
+pub struct Frame<'a> {
+    pub kind: MessageType,
+    pub payload: &'a [u8],
+}
+impl<'a> Frame<'a> {
+    /// Split the first frame off `data`. Returns the frame and the bytes after it.
+    pub fn decode(data: &'a [u8]) -> Result<(Frame<'a>, &'a [u8]), FrameError> {
+        let (&[version, tag, l0, l1], rest) = data
+            .split_first_chunk::<HEADER_SIZE>()
+            .ok_or(FrameError::Truncated)?;
+        if version != PROTOCOL_VERSION {
+            return Err(FrameError::UnsupportedVersion(version));
+        }
+        let kind = MessageType::from_u8(tag).ok_or(FrameError::UnknownType(tag))?;
+        let len = u16::from_le_bytes([l0, l1]) as usize;
+        let (payload, rest) = rest.split_at_checked(len).ok_or(FrameError::Truncated)?;
+        Ok((Frame { kind, payload }, rest))
+    }
+    /// Decode the payload as `M`. Fails if the frame holds a different message.
+    pub fn parse<M: Message<'a>>(&self) -> Result<M, FrameError> {
+        if self.kind != M::TYPE {
+            return Err(FrameError::UnexpectedType(self.kind));
+        }
+        M::decode_exact(self.payload).ok_or(FrameError::Malformed)
+    }
+}
+
+/// Write `msg` with its frame header into `buf`.
+pub fn encode_message<'a, 'b, M: Message<'a>>(msg: &M, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+    let (header, payload) = buf.split_at_mut_checked(HEADER_SIZE)?;
+    let len = msg.encode(payload)?.len();
+    let len = u16::try_from(len).ok()?;
+    let [l0, l1] = len.to_le_bytes();
+    header.copy_from_slice(&[PROTOCOL_VERSION, M::TYPE as u8, l0, l1]);
+    Some(&buf[..HEADER_SIZE + len as usize])
+}
+
+/// Decode a single framed message of type `M` that spans all of `data`.
+pub fn decode_message<'a, M: Message<'a>>(data: &'a [u8]) -> Result<M, FrameError> {
+    match Frame::decode(data)? {
+        (frame, []) => frame.parse(),
+        _ => Err(FrameError::Malformed),
     }
 }
 
 pub trait Data<'a>: Sized {
+    /// Upper bound of the encoded size. `write` fails for values that would exceed it.
     const SIZE: usize;
     fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]>;
+    /// Decode a value from the start of `data` and return the remaining bytes.
     fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])>;
 
+    /// Decode a value that spans all of `data`. Trailing bytes are an error.
+    fn decode_exact(data: &'a [u8]) -> Option<Self> {
+        match Self::decode(data)? {
+            (value, []) => Some(value),
+            _ => None,
+        }
+    }
+
     fn encode<'b>(&self, buf: &'b mut [u8]) -> Option<&'b [u8]> {
         let full = buf.len();
         let rest = self.write(buf)?;
@@ -68,27 +452,89 @@ pub trait Data<'a>: Sized {
         Some(&buf[..written])
     }
 }
-impl<'a> Data<'a> for &'a str {
-    const SIZE: usize = 128;
+/// Longest `&str` or `&[u8]` field, so that the encoded size including the
+/// length prefix is 128 bytes. Use `Str` or `Bytes` for a different limit.
+pub const DEFAULT_MAX_LEN: usize = 126;
+
+/// A string of at most `MAX` bytes, prefixed with a `u16` length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Str<'a, const MAX: usize>(pub &'a str);
+
+/// Bytes of at most `MAX` bytes, prefixed with a `u16` length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Bytes<'a, const MAX: usize>(pub &'a [u8]);
+
+impl<const MAX: usize> core::ops::Deref for Str<'_, MAX> {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+impl<const MAX: usize> core::ops::Deref for Bytes<'_, MAX> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.0
+    }
+}
+
+impl<'a, const MAX: usize> Data<'a> for Bytes<'a, MAX> {
+    const SIZE: usize = {
+        assert!(MAX <= u16::MAX as usize);
+        2 + MAX
+    };
     fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
-        if self.len() > u16::MAX as usize {
+        if self.len() > MAX {
             return None;
         }
-        let len = self.len() as u16;
-        let (dst, rest) = buf.split_at_mut_checked(2)?;
-        dst.copy_from_slice(&len.to_le_bytes());
+        let rest = (self.len() as u16).write(buf)?;
 
         let (dst, rest) = rest.split_at_mut_checked(self.len())?;
-        dst.copy_from_slice(self.as_bytes());
+        dst.copy_from_slice(self);
 
         Some(rest)
     }
     fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
-        let (len, rest) = data.split_at_checked(2)?;
-        let len = u16::from_le_bytes(len.try_into().unwrap()) as usize;
-        let (s, rest) = rest.split_at_checked(len)?;
-        let s = core::str::from_utf8(s).ok()?;
-        Some((s.into(), rest))
+        let (len, rest) = u16::decode(data)?;
+        if len as usize > MAX {
+            return None;
+        }
+        let (bytes, rest) = rest.split_at_checked(len as usize)?;
+        Some((Bytes(bytes), rest))
+    }
+}
+impl<'a, const MAX: usize> Data<'a> for Str<'a, MAX> {
+    const SIZE: usize = Bytes::<MAX>::SIZE;
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        Bytes::<MAX>(self.as_bytes()).write(buf)
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let (Bytes(bytes), rest) = Bytes::<MAX>::decode(data)?;
+        let s = core::str::from_utf8(bytes).ok()?;
+        Some((Str(s), rest))
+    }
+}
+
+/// At most `DEFAULT_MAX_LEN` bytes.
+impl<'a> Data<'a> for &'a str {
+    const SIZE: usize = Str::<DEFAULT_MAX_LEN>::SIZE;
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        Str::<DEFAULT_MAX_LEN>(self).write(buf)
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let (Str(s), rest) = Str::<DEFAULT_MAX_LEN>::decode(data)?;
+        Some((s, rest))
+    }
+}
+
+/// At most `DEFAULT_MAX_LEN` bytes.
+impl<'a> Data<'a> for &'a [u8] {
+    const SIZE: usize = Bytes::<DEFAULT_MAX_LEN>::SIZE;
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        Bytes::<DEFAULT_MAX_LEN>(self).write(buf)
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let (Bytes(bytes), rest) = Bytes::<DEFAULT_MAX_LEN>::decode(data)?;
+        Some((bytes, rest))
     }
 }
 
@@ -106,3 +552,335 @@ impl<'a, const N: usize> Data<'a> for [u8; N] {
         Some((buf, rest))
     }
 }
+
+/// Borrows the array from the input instead of copying it.
+impl<'a, const N: usize> Data<'a> for &'a [u8; N] {
+    const SIZE: usize = N;
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        (**self).write(buf)
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        data.split_first_chunk::<N>()
+    }
+}
+
+/// Values of variable length, which `#[data(max_len = N)]` can be applied to.
+pub trait VarLen {
+    /// Size of the length prefix.
+    const PREFIX: usize;
+    /// Encoded size of one unit of length.
+    const UNIT: usize;
+    /// Limit of the type itself. `max_len` can only make it smaller.
+    const MAX: usize;
+    /// Length in units, i.e. bytes or items.
+    fn var_len(&self) -> usize;
+}
+impl VarLen for &str {
+    const PREFIX: usize = 2;
+    const UNIT: usize = 1;
+    const MAX: usize = DEFAULT_MAX_LEN;
+    fn var_len(&self) -> usize {
+        self.len()
+    }
+}
+impl VarLen for &[u8] {
+    const PREFIX: usize = 2;
+    const UNIT: usize = 1;
+    const MAX: usize = DEFAULT_MAX_LEN;
+    fn var_len(&self) -> usize {
+        self.len()
+    }
+}
+impl<const MAX: usize> VarLen for Str<'_, MAX> {
+    const PREFIX: usize = 2;
+    const UNIT: usize = 1;
+    const MAX: usize = MAX;
+    fn var_len(&self) -> usize {
+        self.len()
+    }
+}
+impl<const MAX: usize> VarLen for Bytes<'_, MAX> {
+    const PREFIX: usize = 2;
+    const UNIT: usize = 1;
+    const MAX: usize = MAX;
+    fn var_len(&self) -> usize {
+        self.len()
+    }
+}
+impl<'a, T: Data<'a> + Clone, const MAX: usize> VarLen for Seq<'a, T, MAX> {
+    const PREFIX: usize = 2;
+    const UNIT: usize = T::SIZE;
+    const MAX: usize = MAX;
+    fn var_len(&self) -> usize {
+        self.len()
+    }
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(
+            /// Little endian.
+            impl<'a> Data<'a> for $t {
+                const SIZE: usize = size_of::<$t>();
+                fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+                    self.to_le_bytes().write(buf)
+                }
+                fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+                    let (bytes, rest) = <[u8; size_of::<$t>()]>::decode(data)?;
+                    Some((<$t>::from_le_bytes(bytes), rest))
+                }
+            }
+        )*
+    };
+}
+impl_int!(u8, u16, u32, u64);
+
+impl<'a> Data<'a> for bool {
+    const SIZE: usize = 1;
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        (*self as u8).write(buf)
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        match u8::decode(data)? {
+            (0, rest) => Some((false, rest)),
+            (1, rest) => Some((true, rest)),
+            _ => None,
+        }
+    }
+}
+
+/// A `bool` tag followed by the value if present.
+impl<'a, T: Data<'a>> Data<'a> for Option<T> {
+    const SIZE: usize = 1 + T::SIZE;
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        let buf = self.is_some().write(buf)?;
+        match self {
+            Some(value) => value.write(buf),
+            None => Some(buf),
+        }
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        match bool::decode(data)? {
+            (true, rest) => {
+                let (value, rest) = T::decode(rest)?;
+                Some((Some(value), rest))
+            }
+            (false, rest) => Some((None, rest)),
+        }
+    }
+}
+
+/// Up to `MAX` items, prefixed with a `u16` count.
+///
+/// Built from a slice for encoding. Decoding only checks the items and keeps the
+/// encoded bytes, `iter` decodes them again. This way no allocation is needed.
+#[derive(Copy, Clone)]
+pub enum Seq<'a, T, const MAX: usize> {
+    Items(&'a [T]),
+    Encoded { len: u16, data: &'a [u8] },
+}
+impl<'a, T: Data<'a> + Clone, const MAX: usize> Seq<'a, T, MAX> {
+    pub fn len(&self) -> usize {
+        match *self {
+            Seq::Items(items) => items.len(),
+            Seq::Encoded { len, .. } => len as usize,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn iter(&self) -> impl Iterator<Item = T> + 'a {
+        let (items, mut data) = match *self {
+            Seq::Items(items) => (items, &[][..]),
+            Seq::Encoded { data, .. } => (&[][..], data),
+        };
+        let decoded = core::iter::from_fn(move || {
+            let (item, rest) = T::decode(data)?;
+            data = rest;
+            Some(item)
+        });
+        items.iter().cloned().chain(decoded)
+    }
+}
+impl<'a, T: Data<'a>, const MAX: usize> Data<'a> for Seq<'a, T, MAX> {
+    const SIZE: usize = {
+        assert!(MAX <= u16::MAX as usize);
+        2 + MAX * T::SIZE
+    };
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        match *self {
+            Seq::Items(items) => {
+                if items.len() > MAX {
+                    return None;
+                }
+                let mut buf = (items.len() as u16).write(buf)?;
+                for item in items {
+                    buf = item.write(buf)?;
+                }
+                Some(buf)
+            }
+            Seq::Encoded { len, data } => {
+                let buf = len.write(buf)?;
+                let (dst, rest) = buf.split_at_mut_checked(data.len())?;
+                dst.copy_from_slice(data);
+                Some(rest)
+            }
+        }
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let (len, items) = u16::decode(data)?;
+        if len as usize > MAX {
+            return None;
+        }
+        let mut rest = items;
+        for _ in 0..len {
+            rest = T::decode(rest)?.1;
+        }
+        let data = &items[..items.len() - rest.len()];
+        Some((Seq::Encoded { len, data }, rest))
+    }
+}
+
+#[test]
+fn test_data_types() {
+    microserde! {
+        pub struct Inner<'a> {
+            pub name: &'a str,
+            pub flag: bool,
+        }
+
+        pub enum Value<'a> {
+            Empty = 0,
+            Number(u64) = 1,
+            Inner(Inner<'a>) = 2,
+        }
+
+        pub struct Outer<'a> {
+            pub value: Value<'a>,
+            pub count: Option<u16>,
+            pub bytes: &'a [u8],
+            pub list: Seq<'a, u32, 4>,
+        }
+    }
+    assert_eq!(Value::SIZE, 1 + 128 + 1);
+
+    let mut buf = [0; Outer::SIZE];
+    let outer = Outer {
+        value: Value::Inner(Inner { name: "name", flag: true }),
+        count: Some(3),
+        bytes: &[1, 2, 3],
+        list: Seq::Items(&[7, 8]),
+    };
+    let data = outer.encode(&mut buf).unwrap();
+    let (decoded, rest) = Outer::decode(data).unwrap();
+    assert!(rest.is_empty());
+    let Value::Inner(inner) = decoded.value else { panic!() };
+    assert_eq!((inner.name, inner.flag), ("name", true));
+    assert_eq!(decoded.count, Some(3));
+    assert_eq!(decoded.bytes, &[1, 2, 3]);
+    assert!(decoded.list.iter().eq([7, 8]));
+
+    assert!(Value::decode(&[3]).is_none());
+    assert!(bool::decode(&[2]).is_none());
+    assert!(Seq::<u32, 4>::Items(&[0; 5]).encode(&mut buf).is_none());
+    assert!(Seq::<u32, 4>::decode(&[5, 0]).is_none());
+}
+
+#[test]
+fn test_derive() {
+    #[derive(Data, Debug, PartialEq)]
+    struct Named<'a> {
+        #[data(max_len = 8)]
+        name: &'a str,
+        id: u32,
+        #[data(default)]
+        extra: Option<u16>,
+    }
+
+    #[derive(Data, Debug, PartialEq)]
+    struct Tuple<T>(T, bool);
+
+    #[derive(Data, Debug, PartialEq)]
+    enum Kind<'a> {
+        #[data(tag = 3)]
+        Unit,
+        #[data(tag = 4)]
+        Tuple(Tuple<u8>),
+        #[data(tag = 5)]
+        Named { inner: Named<'a> },
+    }
+
+    assert_eq!(Named::SIZE, 2 + 8 + 4 + 3);
+    assert_eq!(Kind::SIZE, 1 + Named::SIZE);
+
+    let mut buf = [0; Kind::SIZE];
+    let value = Kind::Named { inner: Named { name: "abc", id: 7, extra: Some(1) } };
+    let data = value.encode(&mut buf).unwrap();
+    assert_eq!(Kind::decode(data), Some((value, &[][..])));
+
+    let value = Kind::Tuple(Tuple(9, true));
+    let data = value.encode(&mut buf).unwrap();
+    assert_eq!(data, [4, 9, 1]);
+    assert_eq!(Kind::decode(&[3]), Some((Kind::Unit, &[][..])));
+    assert_eq!(Kind::decode(&[6]), None);
+
+    // a missing trailing field takes its default
+    let (named, _) = Named::decode(&[1, 0, b'x', 2, 0, 0, 0]).unwrap();
+    assert_eq!(named, Named { name: "x", id: 2, extra: None });
+
+    assert!(Named { name: "too long name", id: 0, extra: None }.encode(&mut buf).is_none());
+    assert!(Named::decode(&[9, 0, b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h', b'i', 0, 0, 0, 0]).is_none());
+    assert_eq!(ErrorCode::decode(&[3]).map(|(c, _)| c), Some(ErrorCode::AuthFailed));
+}
+
+#[test]
+fn test_strict() {
+    let mut buf = [0; 256];
+    let long = core::str::from_utf8(&[b'a'; DEFAULT_MAX_LEN + 1]).unwrap();
+    assert!(long.encode(&mut buf).is_none());
+    assert!((&long[1..]).encode(&mut buf).is_some());
+    assert_eq!(<&str>::SIZE, 2 + DEFAULT_MAX_LEN);
+
+    let data = Str::<200>(long).encode(&mut buf).unwrap().len();
+    assert!(<&str>::decode(&buf[..data]).is_none());
+    assert_eq!(Str::<200>::decode_exact(&buf[..data]), Some(Str(long)));
+    assert!(Str::<200>::decode_exact(&buf[..data + 1]).is_none());
+    assert!(Str::<4>("hello").encode(&mut buf).is_none());
+}
+
+#[test]
+fn test_borrowed() {
+    let a_pub = [7; 512];
+    let mut buf = [0; PreAuthReq::FRAME_SIZE];
+    let data = encode_message(&PreAuthReq { a_pub: &a_pub, username: "user", suites: Suites::all() }, &mut buf).unwrap();
+    let req: PreAuthReq = decode_message(data).unwrap();
+    assert_eq!(req.a_pub, &a_pub);
+    assert!(core::ptr::eq(req.a_pub.as_ptr(), data[HEADER_SIZE..].as_ptr()));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json() {
+    let a_pub = [7; 512];
+    let req = PreAuthReq { a_pub: &a_pub, username: "user", suites: Suites::all() };
+    let text = serde_json::to_string(&req).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(value["username"], "user");
+    assert_eq!(value["suites"], 6);
+    assert!(value["a_pub"].as_str().unwrap().starts_with("BwcH"));
+
+    let owned: json::PreAuthReq = serde_json::from_str(&text).unwrap();
+    assert_eq!(owned.as_message().a_pub, &a_pub);
+    assert_eq!(serde_json::to_string(&json::PreAuthReq::from(req)).unwrap(), text);
+
+    let resp = ErrorResp::new(ErrorCode::TooManyAttempts).with_retry_after(30);
+    let text = serde_json::to_string(&resp).unwrap();
+    assert_eq!(text, r#"{"code":4,"retry_after":30}"#);
+    assert!(serde_json::from_str::<ErrorResp>(r#"{"code":99,"retry_after":null}"#).is_err());
+
+    let ticket = Ticket { ticket: Bytes(&[0xff; 3]), expires: 1000 };
+    let text = serde_json::to_string(&ticket).unwrap();
+    assert_eq!(text, r#"{"ticket":"////","expires":1000}"#);
+    let owned: json::Ticket = serde_json::from_str(&text).unwrap();
+    assert_eq!(owned.as_message().ticket, ticket.ticket);
+}
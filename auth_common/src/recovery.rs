@@ -0,0 +1,225 @@
+//! Format and hash of recovery codes, and the format of recovery keys.
+//!
+//! A code is 80 random bits as 16 characters of Crockford's base32, in groups
+//! of four: `7kq2-ma9x-0c4t-h8vr`. Entry ignores case, dashes and spaces, and
+//! reads `o` as `0` and `i` or `l` as `1`. The server only keeps a salted
+//! BLAKE2b hash of each code, 80 bits are too many to guess.
+//!
+//! A recovery key is a second password next to the real one, for a login that
+//! may only reset the password. It is 128 random bits and a 32 bit checksum in
+//! the same format, 32 characters long, so a typo shows before the login.
+//! `RecoveryKey::as_str` is the password for SRP.
+//!
+//! With the `serde` feature, `RecoveryCodes` is the stored form of the hashes.
+use blake2::{Blake2b512, Digest};
+
+/// Length of a formatted code.
+pub const CODE_LEN: usize = 19;
+/// Length of a formatted recovery key.
+pub const KEY_LEN: usize = 39;
+
+const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct RecoveryCode([u8; CODE_LEN]);
+impl RecoveryCode {
+    /// The code for 10 random bytes.
+    pub fn from_bytes(bytes: [u8; 10]) -> RecoveryCode {
+        let mut out = [b'-'; CODE_LEN];
+        format(&bytes, &mut out);
+        RecoveryCode(out)
+    }
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap()
+    }
+}
+impl core::fmt::Display for RecoveryCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+/// Does not print the code.
+impl core::fmt::Debug for RecoveryCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("RecoveryCode(..)")
+    }
+}
+
+/// The 10 bytes of a code as the user typed it, or `None` if it is not one.
+pub fn parse_code(code: &str) -> Option<[u8; 10]> {
+    let mut bytes = [0; 10];
+    parse(code, &mut bytes)?;
+    Some(bytes)
+}
+
+/// The hash the server stores for a code, `None` if `code` is not one.
+pub fn hash_code(salt: &[u8; 16], code: &str) -> Option<[u8; 32]> {
+    let bytes = parse_code(code)?;
+    let hash = Blake2b512::new()
+        .chain_update(b"auth recovery code")
+        .chain_update(salt)
+        .chain_update(bytes)
+        .finalize();
+    Some(hash[..32].try_into().unwrap())
+}
+
+#[cfg(feature = "serde")]
+#[derive(Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+struct CodeHash(#[serde(with = "serdapt_base64::StdBase64Array")] [u8; 32]);
+
+/// The hashes of the codes of a user that were not used yet.
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct RecoveryCodes {
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    salt: [u8; 16],
+    hashes: alloc::vec::Vec<CodeHash>,
+}
+#[cfg(feature = "serde")]
+impl RecoveryCodes {
+    /// `count` new codes and their hashes. The codes are not kept anywhere.
+    pub fn generate<R: rand_core::CryptoRng + ?Sized>(rng: &mut R, count: usize) -> (alloc::vec::Vec<RecoveryCode>, RecoveryCodes) {
+        let mut salt = [0; 16];
+        rng.fill_bytes(&mut salt);
+        let codes: alloc::vec::Vec<_> = (0..count)
+            .map(|_| {
+                let mut bytes = [0; 10];
+                rng.fill_bytes(&mut bytes);
+                RecoveryCode::from_bytes(bytes)
+            })
+            .collect();
+        let hashes = codes.iter().map(|code| CodeHash(hash_code(&salt, code.as_str()).unwrap())).collect();
+        (codes, RecoveryCodes { salt, hashes })
+    }
+    /// How many codes are left.
+    pub fn remaining(&self) -> usize {
+        self.hashes.len()
+    }
+    /// Use up `code`. Returns whether it was one of the remaining codes.
+    pub fn redeem(&mut self, code: &str) -> bool {
+        let Some(hash) = hash_code(&self.salt, code) else { return false };
+        let before = self.hashes.len();
+        self.hashes.retain(|h| *h != CodeHash(hash));
+        before != self.hashes.len()
+    }
+}
+/// Does not print the hashes.
+#[cfg(feature = "serde")]
+impl core::fmt::Debug for RecoveryCodes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RecoveryCodes").field("remaining", &self.remaining()).finish_non_exhaustive()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct RecoveryKey([u8; KEY_LEN]);
+impl RecoveryKey {
+    /// The key for 16 random bytes.
+    pub fn from_bytes(bytes: [u8; 16]) -> RecoveryKey {
+        let mut full = [0; 20];
+        full[..16].copy_from_slice(&bytes);
+        full[16..].copy_from_slice(&checksum(&bytes));
+        let mut out = [b'-'; KEY_LEN];
+        format(&full, &mut out);
+        RecoveryKey(out)
+    }
+    /// The key as the user typed it, or `None` if it is not one or the
+    /// checksum does not match.
+    pub fn parse(key: &str) -> Option<RecoveryKey> {
+        let mut full = [0; 20];
+        parse(key, &mut full)?;
+        let (bytes, sum) = full.split_at(16);
+        let bytes: [u8; 16] = bytes.try_into().unwrap();
+        (checksum(&bytes) == sum).then(|| RecoveryKey::from_bytes(bytes))
+    }
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap()
+    }
+}
+impl core::fmt::Display for RecoveryKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+/// Does not print the key.
+impl core::fmt::Debug for RecoveryKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("RecoveryKey(..)")
+    }
+}
+
+fn checksum(bytes: &[u8; 16]) -> [u8; 4] {
+    let hash = Blake2b512::new().chain_update(b"auth recovery key").chain_update(bytes).finalize();
+    hash[..4].try_into().unwrap()
+}
+
+/// Write `bytes`, a multiple of 5 long, into `out` in groups of four symbols.
+/// `out` is filled with dashes already.
+fn format(bytes: &[u8], out: &mut [u8]) {
+    let (mut acc, mut bits, mut i) = (0u32, 0, 0);
+    for &b in bytes {
+        acc = (acc << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out[i + i / 4] = ALPHABET[(acc >> bits) as usize & 31];
+            i += 1;
+        }
+    }
+}
+
+/// Read exactly `out.len()` bytes from the symbols of `input`.
+fn parse(input: &str, out: &mut [u8]) -> Option<()> {
+    let (mut acc, mut bits, mut len) = (0u32, 0, 0);
+    for c in input.bytes().filter(|c| !matches!(c, b'-' | b' ')) {
+        let c = match c.to_ascii_lowercase() {
+            b'o' => b'0',
+            b'i' | b'l' => b'1',
+            c => c,
+        };
+        let symbol = ALPHABET.iter().position(|&a| a == c)?;
+        acc = (acc << 5) | symbol as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            *out.get_mut(len)? = (acc >> bits) as u8;
+            len += 1;
+        }
+    }
+    (len == out.len() && bits == 0).then_some(())
+}
+
+#[test]
+fn test_recovery_code() {
+    let code = RecoveryCode::from_bytes([0xa5; 10]);
+    assert_eq!(code.as_str(), "mpjt-b9d5-mpjt-b9d5");
+    assert_eq!(parse_code(code.as_str()), Some([0xa5; 10]));
+    assert_eq!(RecoveryCode::from_bytes([0; 10]).as_str(), "0000-0000-0000-0000");
+    assert_eq!(RecoveryCode::from_bytes([0xff; 10]).as_str(), "zzzz-zzzz-zzzz-zzzz");
+
+    // sloppy entry still counts
+    let hash = hash_code(&[1; 16], "1000-0000-0000-0000");
+    assert!(hash.is_some());
+    assert_eq!(hash_code(&[1; 16], "L000 o000 0OOO 0000"), hash);
+    assert_ne!(hash_code(&[2; 16], "1000-0000-0000-0000"), hash);
+    assert_eq!(hash_code(&[1; 16], "1000-0000-0000-000"), None);
+    assert_eq!(hash_code(&[1; 16], "1000-0000-0000-00000"), None);
+    assert_eq!(hash_code(&[1; 16], "1000-0000-0000-000u"), None);
+}
+
+#[test]
+fn test_recovery_key() {
+    let key = RecoveryKey::from_bytes([7; 16]);
+    assert_eq!(key.as_str().len(), KEY_LEN);
+    assert_eq!(&key.as_str()[..19], "0w3g-e1r7-0w3g-e1r7");
+    assert_eq!(RecoveryKey::parse(key.as_str()), Some(key));
+    assert_eq!(RecoveryKey::parse(&key.as_str().to_uppercase().replace('-', "")), Some(key));
+
+    // a typo fails the checksum
+    let mut typo = key.as_str().as_bytes().to_vec();
+    typo[5] = if typo[5] == b'x' { b'y' } else { b'x' };
+    assert_eq!(RecoveryKey::parse(core::str::from_utf8(&typo).unwrap()), None);
+    assert_eq!(RecoveryKey::parse(&key.as_str()[..38]), None);
+    assert_ne!(RecoveryKey::from_bytes([8; 16]), key);
+}
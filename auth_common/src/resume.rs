@@ -0,0 +1,154 @@
+//! Resumption of a login without another SRP exchange.
+//!
+//! After a login both sides derive a `ResumeSecret` from the shared key. The
+//! server seals it into a ticket only it can open and hands that to the
+//! client. To resume, the client sends the ticket, a fresh `DhShare` and a
+//! MAC keyed with the secret. The server answers with its own share and MAC
+//! and a new ticket. Both then derive the shared key of the resumed login and
+//! the secret of the next ticket from the old secret and the Diffie-Hellman
+//! result of the two shares, and drop the old secret. Each ticket works once.
+//!
+//! The secret travels in the ticket, so whoever gets the ticket key of the
+//! server can open recorded tickets. The shares are thrown away after each
+//! resumption, so that is not enough to derive the keys of the resumptions
+//! made with them. The ticket key still lets its holder resume as any user,
+//! the server has to rotate it.
+use blake2::{Blake2b512, Digest};
+use hkdf::SimpleHkdf;
+use hmac::{Mac, SimpleHmac};
+use srp::{
+    Encoding, U256, U4096,
+    groups::g_4096::{G_MOD_N, N, mod_n},
+};
+use subtle::ConstantTimeEq;
+
+use crate::HandshakeError;
+
+/// Longest ticket a server may hand out.
+pub const MAX_TICKET_LEN: usize = 512;
+
+/// Ephemeral Diffie-Hellman share in the group of the login. Each side makes
+/// a new one for every resumption. The exponent has 256 bits, which keeps
+/// this much cheaper than the exponentiations of a login.
+pub struct DhShare {
+    secret: U256,
+    public: [u8; 512],
+}
+impl DhShare {
+    /// `secret` has to come from a cryptographic RNG.
+    pub fn new(secret: [u8; 32]) -> DhShare {
+        let secret = U256::from_le_bytes(secret);
+        DhShare { secret, public: G_MOD_N.pow(&secret).retrieve().to_le_bytes() }
+    }
+    /// The share to send to the peer.
+    pub fn public(&self) -> &[u8; 512] {
+        &self.public
+    }
+    /// The shared value with the share of the peer. `1`, `N - 1` and values
+    /// outside the group would fix the result and are refused.
+    pub fn agree(&self, peer: &[u8; 512]) -> Result<[u8; 512], HandshakeError> {
+        let peer = U4096::from_le_bytes(*peer);
+        if peer <= U4096::ONE || peer >= N.wrapping_sub(&U4096::ONE) {
+            return Err(HandshakeError::IllegalParameter);
+        }
+        Ok(mod_n(&peer).pow(&self.secret).retrieve().to_le_bytes())
+    }
+}
+
+/// Secret both sides hold for the next resumption.
+#[derive(Clone)]
+pub struct ResumeSecret {
+    key: [u8; 32],
+}
+impl ResumeSecret {
+    pub fn new(shared_key: &[u8; 512]) -> ResumeSecret {
+        let mut key = [0; 32];
+        SimpleHkdf::<Blake2b512>::new(None, shared_key)
+            .expand(b"auth resumption", &mut key)
+            .expect("32 bytes is a valid length");
+        ResumeSecret { key }
+    }
+    pub fn from_bytes(key: [u8; 32]) -> ResumeSecret {
+        ResumeSecret { key }
+    }
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// MAC of the client over the ticket and its share.
+    pub fn client_proof(&self, ticket: &[u8], client_share: &[u8; 512]) -> [u8; 32] {
+        self.mac(b"client", ticket, client_share, &[0; 512])
+    }
+    /// MAC of the server, which also covers its share.
+    pub fn server_proof(&self, ticket: &[u8], client_share: &[u8; 512], server_share: &[u8; 512]) -> [u8; 32] {
+        self.mac(b"server", ticket, client_share, server_share)
+    }
+    pub fn verify_client(&self, ticket: &[u8], client_share: &[u8; 512], proof: &[u8; 32]) -> bool {
+        self.client_proof(ticket, client_share).ct_eq(proof).into()
+    }
+    pub fn verify_server(&self, ticket: &[u8], client_share: &[u8; 512], server_share: &[u8; 512], proof: &[u8; 32]) -> bool {
+        self.server_proof(ticket, client_share, server_share).ct_eq(proof).into()
+    }
+
+    /// The shared key of the resumed login and the secret of the next ticket.
+    /// `dh` is the result of `DhShare::agree`.
+    pub fn next(&self, client_share: &[u8; 512], server_share: &[u8; 512], dh: &[u8; 512]) -> ([u8; 512], ResumeSecret) {
+        let salt = Blake2b512::new().chain_update(client_share).chain_update(server_share).finalize();
+        let mut ikm = [0; 32 + 512];
+        ikm[..32].copy_from_slice(&self.key);
+        ikm[32..].copy_from_slice(dh);
+        let hkdf = SimpleHkdf::<Blake2b512>::new(Some(&salt), &ikm);
+        let mut shared_key = [0; 512];
+        hkdf.expand(b"auth resumption key", &mut shared_key).expect("512 bytes is a valid length");
+        let mut key = [0; 32];
+        hkdf.expand(b"auth resumption next", &mut key).expect("32 bytes is a valid length");
+        (shared_key, ResumeSecret { key })
+    }
+
+    fn mac(&self, label: &[u8], ticket: &[u8], client_share: &[u8; 512], server_share: &[u8; 512]) -> [u8; 32] {
+        let mut mac = SimpleHmac::<Blake2b512>::new_from_slice(&self.key).expect("any key length works");
+        mac.update(label);
+        mac.update(&Blake2b512::digest(ticket));
+        mac.update(client_share);
+        mac.update(server_share);
+        let mut out = [0; 32];
+        out.copy_from_slice(&mac.finalize().into_bytes()[..32]);
+        out
+    }
+}
+
+#[test]
+fn test_resume_secret() {
+    let client = ResumeSecret::new(&[7; 512]);
+    let server = ResumeSecret::from_bytes(*client.as_bytes());
+    let proof = client.client_proof(b"ticket", &[1; 512]);
+    assert!(server.verify_client(b"ticket", &[1; 512], &proof));
+    assert!(!server.verify_client(b"other", &[1; 512], &proof));
+    assert!(!server.verify_client(b"ticket", &[2; 512], &proof));
+    assert!(!ResumeSecret::new(&[8; 512]).verify_client(b"ticket", &[1; 512], &proof));
+
+    // a client MAC does not pass as the server's
+    let proof = server.server_proof(b"ticket", &[1; 512], &[0; 512]);
+    assert!(client.verify_server(b"ticket", &[1; 512], &[0; 512], &proof));
+    assert!(!client.verify_client(b"ticket", &[1; 512], &proof));
+
+    let (key, next) = client.next(&[1; 512], &[2; 512], &[4; 512]);
+    assert_eq!(server.next(&[1; 512], &[2; 512], &[4; 512]).0, key);
+    assert_ne!(client.next(&[1; 512], &[3; 512], &[4; 512]).0, key);
+    // the secret alone does not give the key
+    assert_ne!(client.next(&[1; 512], &[2; 512], &[5; 512]).0, key);
+    assert_ne!(next.as_bytes(), client.as_bytes());
+    assert_ne!(key, [7; 512]);
+}
+
+#[test]
+fn test_dh_share() {
+    let (client, server) = (DhShare::new([1; 32]), DhShare::new([2; 32]));
+    assert_eq!(client.agree(server.public()), server.agree(client.public()));
+    assert_ne!(client.agree(server.public()), client.agree(DhShare::new([3; 32]).public()));
+
+    let minus_one = N.wrapping_sub(&U4096::ONE).to_le_bytes();
+    for share in [[0; 512], U4096::ONE.to_le_bytes(), minus_one, N.to_le_bytes(), [0xff; 512]] {
+        assert_eq!(client.agree(&share), Err(HandshakeError::IllegalParameter));
+    }
+}
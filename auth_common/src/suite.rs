@@ -0,0 +1,81 @@
+//! Algorithm suites a verifier can be registered with.
+//!
+//! Every suite uses the 4096-bit group from RFC 5054, it is the only one `srp`
+//! implements. Suites differ in the digest used by SRP and the password hasher.
+use blake2::{Blake2b512, Blake2s256};
+use srp::{A2, A2id, Encoding, client::SrpClient4096};
+
+use crate::Data;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default, Data)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "u8", try_from = "u8"))]
+#[repr(u8)]
+pub enum Suite {
+    /// Blake2b-512 and Argon2d. Used by every client before suites were negotiated.
+    #[default]
+    Blake2bArgon2d = 1,
+    /// Blake2s-256 and Argon2id.
+    Blake2sArgon2id = 2,
+}
+impl Suite {
+    pub const ALL: [Suite; 2] = [Suite::Blake2bArgon2d, Suite::Blake2sArgon2id];
+
+    pub fn from_u8(tag: u8) -> Option<Self> {
+        Suite::ALL.into_iter().find(|&s| s as u8 == tag)
+    }
+
+    /// Output size of the SRP digest, which is also the length of the proofs.
+    /// `AuthReq` and `AuthResponse` zero pad shorter proofs to 64 bytes.
+    pub fn proof_len(self) -> usize {
+        match self {
+            Suite::Blake2bArgon2d => 64,
+            Suite::Blake2sArgon2id => 32,
+        }
+    }
+
+    /// Compute the password verifier to put into a `RegisterReq`.
+    pub fn compute_verifier(self, username: &[u8], password: &[u8], salt: &[u8]) -> [u8; 512] {
+        let v = match self {
+            Suite::Blake2bArgon2d => {
+                SrpClient4096::<A2, Blake2b512>::compute_verifier(username, password, salt)
+            }
+            Suite::Blake2sArgon2id => {
+                SrpClient4096::<A2id, Blake2s256>::compute_verifier(username, password, salt)
+            }
+        };
+        v.to_le_bytes()
+    }
+}
+
+/// Set of suites a client supports.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Suites(u16);
+impl Suites {
+    pub const NONE: Suites = Suites(0);
+
+    pub fn all() -> Suites {
+        Suite::ALL.into_iter().fold(Suites::NONE, Suites::with)
+    }
+    pub fn with(self, suite: Suite) -> Suites {
+        Suites(self.0 | 1 << suite as u8)
+    }
+    pub fn contains(self, suite: Suite) -> bool {
+        self.0 & 1 << suite as u8 != 0
+    }
+    pub fn iter(self) -> impl Iterator<Item = Suite> {
+        Suite::ALL.into_iter().filter(move |&s| self.contains(s))
+    }
+}
+
+/// Unknown bits are kept, so a newer client does not fail to decode.
+impl<'a> Data<'a> for Suites {
+    const SIZE: usize = 2;
+    fn write<'b>(&self, buf: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        self.0.to_le_bytes().write(buf)
+    }
+    fn decode(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let (bits, rest) = <[u8; 2]>::decode(data)?;
+        Some((Suites(u16::from_le_bytes(bits)), rest))
+    }
+}
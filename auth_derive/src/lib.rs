@@ -0,0 +1,322 @@
+//! `#[derive(Data)]` for `auth_common::Data`.
+//!
+//! Fields are written in declaration order, enums start with a one byte tag.
+//!
+//! Field attributes:
+//! - `#[data(max_len = N)]` limits a `&str`, `&[u8]`, `Str`, `Bytes` or `Seq` field
+//!   to `N` bytes or items. The limit is checked when writing and decoding, and
+//!   `SIZE` uses it. It can not exceed the limit of the type itself.
+//! - `#[data(default)]` decodes the field as `Default::default()` when the input
+//!   ends before it. Only trailing fields can have a default, this is how fields
+//!   get added to a message without breaking old peers.
+//!
+//! Variant attributes:
+//! - `#[data(tag = N)]` sets the tag of an enum variant. An explicit discriminant
+//!   (`Variant = N`) works too.
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2, TokenTree};
+use quote::{ToTokens, format_ident, quote, quote_spanned};
+use syn::{
+    Data, DeriveInput, Error, Expr, ExprLit, Fields, GenericParam, Ident, Lifetime, LifetimeParam,
+    Lit, LitInt, Result, Type, parse_macro_input, parse_quote, spanned::Spanned,
+};
+
+#[proc_macro_derive(Data, attributes(data))]
+pub fn derive_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+struct Field {
+    ty: Type,
+    /// Name of a named field.
+    ident: Option<Ident>,
+    /// Local the field is bound to.
+    binding: Ident,
+    max_len: Option<LitInt>,
+    default: bool,
+}
+
+fn parse_fields(fields: &Fields) -> Result<Vec<Field>> {
+    let mut out = Vec::new();
+    let mut default_seen = false;
+    for (i, field) in fields.iter().enumerate() {
+        let mut max_len = None;
+        let mut default = false;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("data")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("max_len") {
+                    max_len = Some(meta.value()?.parse::<LitInt>()?);
+                    Ok(())
+                } else if meta.path.is_ident("default") {
+                    default = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `max_len = N` or `default`"))
+                }
+            })?;
+        }
+        if let Some(max_len) = &max_len {
+            max_len.base10_parse::<usize>()?;
+        }
+        if default {
+            default_seen = true;
+        } else if default_seen {
+            return Err(Error::new_spanned(
+                field,
+                "fields after a `#[data(default)]` field need a default as well",
+            ));
+        }
+        let binding = match &field.ident {
+            Some(ident) => format_ident!("__field_{}", ident),
+            None => format_ident!("__field{}", i),
+        };
+        out.push(Field { ty: field.ty.clone(), ident: field.ident.clone(), binding, max_len, default });
+    }
+    Ok(out)
+}
+
+/// Encoded size of the fields, as a constant expression.
+fn size(fields: &[Field], lt: &Lifetime) -> TokenStream2 {
+    let sizes = fields.iter().map(|f| {
+        let ty = &f.ty;
+        match &f.max_len {
+            Some(max) => quote_spanned! { ty.span()=>
+                {
+                    assert!(#max <= <#ty as ::auth_common::VarLen>::MAX, "max_len exceeds the limit of the type");
+                    <#ty as ::auth_common::VarLen>::PREFIX + #max * <#ty as ::auth_common::VarLen>::UNIT
+                }
+            },
+            None => quote_spanned! { ty.span()=> <#ty as ::auth_common::Data<#lt>>::SIZE },
+        }
+    });
+    quote! { 0 #( + #sizes )* }
+}
+
+/// Statements writing the fields, which are bound to references.
+fn write(fields: &[Field], lt: &Lifetime) -> TokenStream2 {
+    let writes = fields.iter().map(|f| {
+        let Field { ty, binding, .. } = f;
+        let check = f.max_len.as_ref().map(|max| {
+            quote_spanned! { ty.span()=>
+                if <#ty as ::auth_common::VarLen>::var_len(#binding) > #max {
+                    return None;
+                }
+            }
+        });
+        quote_spanned! { ty.span()=>
+            #check
+            let buf = <#ty as ::auth_common::Data<#lt>>::write(#binding, buf)?;
+        }
+    });
+    quote! { #( #writes )* }
+}
+
+/// Statements decoding the fields from `data` into their bindings.
+fn decode(fields: &[Field], lt: &Lifetime) -> TokenStream2 {
+    let decodes = fields.iter().map(|f| {
+        let Field { ty, binding, .. } = f;
+        let decode = quote_spanned! { ty.span()=> <#ty as ::auth_common::Data<#lt>>::decode(data)? };
+        let decode = match f.default {
+            true => quote! {
+                if data.is_empty() {
+                    (::core::default::Default::default(), data)
+                } else {
+                    #decode
+                }
+            },
+            false => decode,
+        };
+        let check = f.max_len.as_ref().map(|max| {
+            quote_spanned! { ty.span()=>
+                if <#ty as ::auth_common::VarLen>::var_len(&#binding) > #max {
+                    return None;
+                }
+            }
+        });
+        quote! {
+            let (#binding, data): (#ty, _) = #decode;
+            #check
+        }
+    });
+    quote! { #( #decodes )* }
+}
+
+/// `Self { a: __field_a }`, `Self(__field0)` or `Self` for the given fields.
+fn construct(path: TokenStream2, fields: &Fields, parsed: &[Field]) -> TokenStream2 {
+    let bindings = parsed.iter().map(|f| &f.binding);
+    match fields {
+        Fields::Named(_) => {
+            let idents = parsed.iter().map(|f| &f.ident);
+            quote! { #path { #( #idents: #bindings ),* } }
+        }
+        Fields::Unnamed(_) => quote! { #path ( #( #bindings ),* ) },
+        Fields::Unit => path,
+    }
+}
+
+fn variant_tag(variant: &syn::Variant) -> Result<u8> {
+    let mut tag = None;
+    for attr in variant.attrs.iter().filter(|a| a.path().is_ident("data")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                tag = Some(meta.value()?.parse::<LitInt>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `tag = N`"))
+            }
+        })?;
+    }
+    if tag.is_none()
+        && let Some((_, Expr::Lit(ExprLit { lit: Lit::Int(lit), .. }))) = &variant.discriminant
+    {
+        tag = Some(lit.clone());
+    }
+    match tag {
+        Some(lit) => lit.base10_parse::<u8>(),
+        None => Err(Error::new_spanned(
+            variant,
+            "missing tag, add `#[data(tag = N)]` or an explicit discriminant",
+        )),
+    }
+}
+
+/// Whether `tokens` contain one of `idents`.
+fn mentions(tokens: TokenStream2, idents: &[Ident]) -> bool {
+    tokens.into_iter().any(|tt| match tt {
+        TokenTree::Ident(ident) => idents.contains(&ident),
+        TokenTree::Group(group) => mentions(group.stream(), idents),
+        _ => false,
+    })
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+
+    // The lifetime of the decoded data is the lifetime parameter of the type,
+    // or a new one if it has none.
+    let mut lifetimes = input.generics.lifetimes();
+    let (lt, mut impl_generics) = match (lifetimes.next(), lifetimes.next()) {
+        (None, _) => {
+            let lt = Lifetime::new("'__data", Span::call_site());
+            let mut generics = input.generics.clone();
+            generics.params.insert(0, GenericParam::Lifetime(LifetimeParam::new(lt.clone())));
+            (lt, generics)
+        }
+        (Some(param), None) => (param.lifetime.clone(), input.generics.clone()),
+        (Some(_), Some(second)) => {
+            return Err(Error::new_spanned(second, "Data supports at most one lifetime"));
+        }
+    };
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    let (size, write, decode, all_fields) = match &input.data {
+        Data::Struct(data) => {
+            let fields = parse_fields(&data.fields)?;
+            let size = size(&fields, &lt);
+            let bind = construct(quote!(Self), &data.fields, &fields);
+            let writes = write(&fields, &lt);
+            let decodes = decode(&fields, &lt);
+            let write = quote! {
+                let #bind = self;
+                #writes
+                Some(buf)
+            };
+            let decode = quote! {
+                #decodes
+                Some((#bind, data))
+            };
+            (size, write, decode, fields)
+        }
+        Data::Enum(data) => {
+            let mut sizes = Vec::new();
+            let mut write_arms = Vec::new();
+            let mut decode_arms = Vec::new();
+            let mut all_fields = Vec::new();
+            let mut tags: Vec<(u8, &syn::Variant)> = Vec::new();
+            for variant in &data.variants {
+                let tag = variant_tag(variant)?;
+                if let Some((_, first)) = tags.iter().find(|(t, _)| *t == tag) {
+                    let mut err = Error::new_spanned(variant, format_args!("duplicate tag {tag}"));
+                    err.combine(Error::new_spanned(first, "first used here"));
+                    return Err(err);
+                }
+                tags.push((tag, variant));
+
+                let ident = &variant.ident;
+                let fields = parse_fields(&variant.fields)?;
+                sizes.push(size(&fields, &lt));
+                let bind = construct(quote!(Self::#ident), &variant.fields, &fields);
+                let writes = write(&fields, &lt);
+                let decodes = decode(&fields, &lt);
+                write_arms.push(quote! {
+                    #bind => {
+                        let buf = <u8 as ::auth_common::Data<#lt>>::write(&#tag, buf)?;
+                        #writes
+                        Some(buf)
+                    }
+                });
+                decode_arms.push(quote! {
+                    #tag => {
+                        #decodes
+                        Some((#bind, data))
+                    }
+                });
+                all_fields.extend(fields);
+            }
+            let size = quote! {
+                1 + {
+                    let sizes = [0 #( , #sizes )*];
+                    let mut max = 0;
+                    let mut i = 0;
+                    while i < sizes.len() {
+                        if sizes[i] > max {
+                            max = sizes[i];
+                        }
+                        i += 1;
+                    }
+                    max
+                }
+            };
+            let write = quote! {
+                match self {
+                    #( #write_arms )*
+                }
+            };
+            let decode = quote! {
+                let (tag, data) = <u8 as ::auth_common::Data<#lt>>::decode(data)?;
+                match tag {
+                    #( #decode_arms )*
+                    _ => None,
+                }
+            };
+            (size, write, decode, all_fields)
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(data.union_token, "Data can not be derived for unions"));
+        }
+    };
+
+    // Only fields that use a type parameter get a bound. The others are checked
+    // in the impl, so an unsupported type is reported at the field.
+    let params: Vec<_> = input.generics.type_params().map(|p| p.ident.clone()).collect();
+    let where_clause = impl_generics.make_where_clause();
+    for field in all_fields.iter().filter(|f| mentions(f.ty.to_token_stream(), &params)) {
+        let ty = &field.ty;
+        where_clause.predicates.push(parse_quote!(#ty: ::auth_common::Data<#lt>));
+    }
+    let (impl_generics, _, where_clause) = impl_generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::auth_common::Data<#lt> for #name #ty_generics #where_clause {
+            const SIZE: usize = #size;
+
+            fn write<'__buf>(&self, buf: &'__buf mut [u8]) -> Option<&'__buf mut [u8]> {
+                #write
+            }
+            fn decode(data: &#lt [u8]) -> Option<(Self, &#lt [u8])> {
+                #decode
+            }
+        }
+    })
+}
@@ -0,0 +1,6 @@
+//! The errors of `#[derive(Data)]` point at the offending tokens. Regenerate the
+//! expected output with `TRYBUILD=overwrite cargo test -p auth_derive`.
+#[test]
+fn ui() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,9 @@
+use auth_common::Data;
+
+#[derive(Data)]
+struct Msg<'a> {
+    #[data(max_len = -1)]
+    name: &'a str,
+}
+
+fn main() {}
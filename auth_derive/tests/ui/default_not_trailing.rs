@@ -0,0 +1,10 @@
+use auth_common::Data;
+
+#[derive(Data)]
+struct Msg {
+    #[data(default)]
+    a: u8,
+    b: u8,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use auth_common::Data;
+
+#[derive(Data)]
+enum Msg {
+    A = 1,
+    #[data(tag = 2)]
+    B,
+    #[data(tag = 1)]
+    C,
+}
+
+fn main() {}
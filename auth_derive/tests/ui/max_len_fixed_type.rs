@@ -0,0 +1,9 @@
+use auth_common::Data;
+
+#[derive(Data)]
+struct Msg {
+    #[data(max_len = 4)]
+    count: u32,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use auth_common::Data;
+
+#[derive(Data)]
+enum Msg {
+    #[data(tag = 1)]
+    A,
+    B(u8),
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use auth_common::Data;
+
+#[derive(Data)]
+struct Msg<'a, 'b> {
+    a: &'a str,
+    b: &'b str,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use auth_common::Data;
+
+#[derive(Data)]
+union Msg {
+    a: u8,
+    b: u16,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use auth_common::Data;
+
+#[derive(Data)]
+struct Msg<'a> {
+    #[data(max = 4)]
+    name: &'a str,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use auth_common::Data;
+
+#[derive(Data)]
+enum Msg {
+    #[data(id = 1)]
+    A,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use auth_common::Data;
+
+#[derive(Data)]
+struct Msg {
+    ok: u32,
+    ratio: f32,
+}
+
+fn main() {}
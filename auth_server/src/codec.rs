@@ -0,0 +1,126 @@
+//! Framing of `auth_common` messages on async byte streams.
+//!
+//! `MessageCodec` plugs into `tokio_util::codec::Framed`. Frames are decoded
+//! into a `RawFrame` that owns its payload, the message is parsed from it with
+//! `RawFrame::parse`, so large fields can still borrow.
+use auth_common::{Frame, FrameError, HEADER_SIZE, Message, MessageType, encode_message};
+use bytes::{Bytes, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{ErrorCode, ErrorResp};
+
+/// Reads and writes framed messages.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MessageCodec;
+
+/// A complete frame read from the stream.
+#[derive(Clone, Debug)]
+pub struct RawFrame {
+    pub kind: MessageType,
+    pub payload: Bytes,
+}
+impl RawFrame {
+    /// Decode the payload as `M`. Fails if the frame holds a different message.
+    pub fn parse<'a, M: Message<'a>>(&'a self) -> Result<M, FrameError> {
+        Frame { kind: self.kind, payload: &self.payload }.parse()
+    }
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(io::Error),
+    /// The peer sent a frame we can not decode. The stream is out of sync after this.
+    Frame(FrameError),
+    /// The message does not fit into a frame.
+    Encode,
+}
+impl CodecError {
+    /// The response to send to the peer, if it is worth answering.
+    pub fn response(&self) -> Option<ErrorResp> {
+        match self {
+            CodecError::Frame(e) => Some(ErrorResp::new(e.code())),
+            CodecError::Encode => Some(ErrorResp::new(ErrorCode::Internal)),
+            CodecError::Io(_) => None,
+        }
+    }
+}
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+impl From<FrameError> for CodecError {
+    fn from(e: FrameError) -> Self {
+        CodecError::Frame(e)
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = RawFrame;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RawFrame>, CodecError> {
+        let (kind, len) = match Frame::decode(src) {
+            Ok((frame, _)) => (frame.kind, frame.payload.len()),
+            Err(FrameError::Truncated) => {
+                // make room for the rest of the frame once the header is in
+                if let [_, _, l0, l1, ..] = **src {
+                    let len = HEADER_SIZE + u16::from_le_bytes([l0, l1]) as usize;
+                    src.reserve(len - src.len());
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut frame = src.split_to(HEADER_SIZE + len);
+        let payload = frame.split_off(HEADER_SIZE).freeze();
+        Ok(Some(RawFrame { kind, payload }))
+    }
+}
+
+impl<'a, M: Message<'a>> Encoder<&M> for MessageCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, msg: &M, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let start = dst.len();
+        dst.resize(start + M::FRAME_SIZE, 0);
+        match encode_message(msg, &mut dst[start..]).map(|frame| frame.len()) {
+            Some(len) => {
+                dst.truncate(start + len);
+                Ok(())
+            }
+            None => {
+                dst.truncate(start);
+                Err(CodecError::Encode)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_codec() {
+    use crate::{AuthReq, AuthResponse};
+
+    let mut codec = MessageCodec;
+    let mut buf = BytesMut::new();
+    codec.encode(&AuthReq { proof: [1; 64], key: [2; 8] }, &mut buf).unwrap();
+    codec.encode(&AuthResponse { proof: [3; 64] }, &mut buf).unwrap();
+
+    // frames arrive in pieces
+    let mut src = BytesMut::new();
+    src.extend_from_slice(&buf[..3]);
+    assert!(codec.decode(&mut src).unwrap().is_none());
+    src.extend_from_slice(&buf[3..40]);
+    assert!(codec.decode(&mut src).unwrap().is_none());
+    src.extend_from_slice(&buf[40..]);
+
+    let frame = codec.decode(&mut src).unwrap().unwrap();
+    assert_eq!(frame.parse::<AuthReq>().unwrap().key, [2; 8]);
+    let frame = codec.decode(&mut src).unwrap().unwrap();
+    assert_eq!(frame.parse::<AuthResponse>().unwrap().proof, [3; 64]);
+    assert!(src.is_empty());
+
+    src.extend_from_slice(&[9, 0, 0, 0]);
+    assert!(matches!(codec.decode(&mut src), Err(CodecError::Frame(FrameError::UnsupportedVersion(9)))));
+}
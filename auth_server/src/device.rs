@@ -0,0 +1,253 @@
+//! Trusted devices, so logins from a known device can skip the second factor.
+//!
+//! After a login the user can trust the device with `Devices::trust`. The
+//! device key comes from the shared key of that login, see
+//! `auth_common::device`, the client keeps it with `auth::TrustedDevice`.
+//! After a later login, `Devices::check` takes the `DeviceProof` the client
+//! sends along. Trust ends after `lifetime`, or when the user revokes the
+//! device. Times are seconds since the Unix epoch.
+//!
+//! This is the library half only. No login message has a place for the
+//! `DeviceProof`, and `router` has no device routes and always asks for the
+//! code. To skip TOTP on endpoints of your own, have the client send the
+//! proof its own way next to the `AuthReq`, e.g. as a second frame, made for
+//! `auth::Proof::unverified_key`. Check it right after `SrpAuth::auth`: if
+//! `Devices::check` accepts it, send the `AuthResponse`, otherwise go on with
+//! `TotpLogins::challenge`. Listing and revoking devices with `Devices::list`
+//! and `Devices::revoke` needs routes of your own as well.
+use std::{collections::HashMap, future::Future, hash::Hash, sync::Mutex};
+
+pub use auth_common::DeviceProof;
+use auth_common::device::DeviceKey;
+use serde::{Deserialize, Serialize};
+
+use crate::Authenticated;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DeviceId(#[serde(with = "serdapt_base64::StdBase64Array")] [u8; 16]);
+impl DeviceId {
+    pub fn from_bytes(bytes: [u8; 16]) -> DeviceId {
+        DeviceId(bytes)
+    }
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+    /// The id as 32 hex digits, e.g. in the path of a revoke request.
+    pub fn to_hex(&self) -> String {
+        crate::to_hex(&self.0)
+    }
+    pub fn from_hex(hex: &str) -> Option<DeviceId> {
+        crate::from_hex(hex).map(DeviceId)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub id: DeviceId,
+    /// Label chosen by the user, e.g. the browser and OS.
+    pub name: String,
+    pub created: u64,
+    pub last_used: u64,
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    key: [u8; 32],
+}
+/// Does not print the key.
+impl std::fmt::Debug for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Device")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("created", &self.created)
+            .field("last_used", &self.last_used)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Storage of the trusted devices of each user.
+pub trait DeviceStore {
+    type User: Send + Sync;
+    fn insert(&self, user: &Self::User, device: Device) -> impl Future<Output = ()> + Send;
+    fn list(&self, user: &Self::User) -> impl Future<Output = Vec<Device>> + Send;
+    /// Record a login from the device.
+    fn touch(&self, user: &Self::User, id: &DeviceId, now: u64) -> impl Future<Output = ()> + Send;
+    /// Returns whether there was such a device.
+    fn remove(&self, user: &Self::User, id: &DeviceId) -> impl Future<Output = bool> + Send;
+    /// Remove all devices of `user` and return how many there were.
+    fn remove_user(&self, user: &Self::User) -> impl Future<Output = usize> + Send;
+}
+
+/// Devices in memory. They are lost on restart.
+pub struct MemoryDevices<U> {
+    devices: Mutex<HashMap<U, Vec<Device>>>,
+}
+impl<U> Default for MemoryDevices<U> {
+    fn default() -> Self {
+        MemoryDevices { devices: Mutex::new(HashMap::new()) }
+    }
+}
+impl<U: Clone + Eq + Hash + Send + Sync> DeviceStore for MemoryDevices<U> {
+    type User = U;
+    async fn insert(&self, user: &U, device: Device) {
+        let mut devices = self.devices.lock().unwrap();
+        let list = devices.entry(user.clone()).or_default();
+        list.retain(|d| d.id != device.id);
+        list.push(device);
+    }
+    async fn list(&self, user: &U) -> Vec<Device> {
+        self.devices.lock().unwrap().get(user).cloned().unwrap_or_default()
+    }
+    async fn touch(&self, user: &U, id: &DeviceId, now: u64) {
+        let mut devices = self.devices.lock().unwrap();
+        if let Some(device) = devices.get_mut(user).and_then(|list| list.iter_mut().find(|d| d.id == *id)) {
+            device.last_used = now;
+        }
+    }
+    async fn remove(&self, user: &U, id: &DeviceId) -> bool {
+        let mut devices = self.devices.lock().unwrap();
+        let Some(list) = devices.get_mut(user) else { return false };
+        let before = list.len();
+        list.retain(|d| d.id != *id);
+        before != list.len()
+    }
+    async fn remove_user(&self, user: &U) -> usize {
+        self.devices.lock().unwrap().remove(user).map_or(0, |list| list.len())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceError {
+    /// The user has no device with this id, or it was revoked.
+    Unknown,
+    /// The device was trusted longer ago than the lifetime.
+    Expired,
+    /// The proof does not match the device key or this login.
+    Invalid,
+}
+
+pub struct Devices<S> {
+    store: S,
+    lifetime: u64,
+}
+impl<S: DeviceStore> Devices<S> {
+    /// Devices stay trusted for 90 days, see `with_lifetime`.
+    pub fn new(store: S) -> Self {
+        Devices { store, lifetime: 90 * 24 * 60 * 60 }
+    }
+    pub fn with_lifetime(self, secs: u64) -> Self {
+        Devices { lifetime: secs, ..self }
+    }
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Trust the device `auth` logged in from.
+    pub async fn trust<D>(&self, auth: &Authenticated<D>, user: &S::User, name: &str, now: u64) -> DeviceId {
+        self.trust_key(&auth.get_key(), user, name, now).await
+    }
+    /// Check the `DeviceProof` sent after the login `auth`.
+    pub async fn check<D>(&self, auth: &Authenticated<D>, user: &S::User, proof: &DeviceProof, now: u64) -> Result<DeviceId, DeviceError> {
+        self.check_key(&auth.get_key(), user, proof, now).await
+    }
+    /// The devices of `user` that are still trusted.
+    pub async fn list(&self, user: &S::User, now: u64) -> Vec<Device> {
+        let mut devices = self.store.list(user).await;
+        devices.retain(|d| !self.expired(d, now));
+        devices
+    }
+    pub async fn revoke(&self, user: &S::User, id: &DeviceId) -> bool {
+        self.store.remove(user, id).await
+    }
+    /// Revoke all devices of `user`, e.g. after a password change.
+    pub async fn revoke_all(&self, user: &S::User) -> usize {
+        self.store.remove_user(user).await
+    }
+
+    async fn trust_key(&self, shared_key: &[u8; 512], user: &S::User, name: &str, now: u64) -> DeviceId {
+        let key = DeviceKey::new(shared_key);
+        let id = DeviceId(key.id());
+        let device = Device { id, name: name.into(), created: now, last_used: now, key: *key.key() };
+        self.store.insert(user, device).await;
+        id
+    }
+    async fn check_key(&self, shared_key: &[u8; 512], user: &S::User, proof: &DeviceProof, now: u64) -> Result<DeviceId, DeviceError> {
+        let id = DeviceId(proof.id);
+        let device = self.store.list(user).await.into_iter().find(|d| d.id == id).ok_or(DeviceError::Unknown)?;
+        if self.expired(&device, now) {
+            self.store.remove(user, &id).await;
+            return Err(DeviceError::Expired);
+        }
+        if !DeviceKey::from_parts(device.id.0, device.key).verify(shared_key, proof) {
+            return Err(DeviceError::Invalid);
+        }
+        self.store.touch(user, &id, now).await;
+        Ok(id)
+    }
+    fn expired(&self, device: &Device, now: u64) -> bool {
+        now >= device.created.saturating_add(self.lifetime)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_devices() {
+    let devices = Devices::new(MemoryDevices::default()).with_lifetime(100);
+    let user = "user".to_owned();
+    let laptop = devices.trust_key(&[1; 512], &user, "laptop", 1000).await;
+    let phone = devices.trust_key(&[2; 512], &user, "phone", 1050).await;
+    assert_eq!(DeviceId::from_hex(&laptop.to_hex()), Some(laptop));
+
+    // a later login from the laptop
+    let proof = DeviceKey::new(&[1; 512]).proof(&[3; 512]);
+    assert_eq!(devices.check_key(&[3; 512], &user, &proof, 1010).await, Ok(laptop));
+    assert_eq!(devices.check_key(&[4; 512], &user, &proof, 1010).await, Err(DeviceError::Invalid));
+    assert_eq!(devices.check_key(&[3; 512], &"other".to_owned(), &proof, 1010).await, Err(DeviceError::Unknown));
+    let names = |list: Vec<Device>| list.into_iter().map(|d| d.name).collect::<Vec<_>>();
+    assert_eq!(names(devices.list(&user, 1020).await), ["laptop", "phone"]);
+    assert_eq!(devices.store().list(&user).await[0].last_used, 1010);
+
+    assert!(devices.revoke(&user, &phone).await);
+    assert!(!devices.revoke(&user, &phone).await);
+    assert_eq!(names(devices.list(&user, 1020).await), ["laptop"]);
+
+    assert_eq!(devices.check_key(&[3; 512], &user, &proof, 1100).await, Err(DeviceError::Expired));
+    assert_eq!(devices.check_key(&[3; 512], &user, &proof, 1010).await, Err(DeviceError::Unknown));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_skip_totp() {
+    use crate::{SrpAuth, UserData, client_login_with, decode_message, encode, totp::{Totp, TotpLogins}};
+
+    let mut user = UserData::test_user("password");
+    let totp = Totp::generate();
+    user.set_totp(Some(&totp), &[2; 32]);
+    let name = "user".to_owned();
+    let devices = Devices::new(MemoryDevices::default());
+    let mut srp = SrpAuth::<u32, String>::new();
+    let mut totp_logins = TotpLogins::new();
+
+    // up to `SrpAuth::auth`, with the `DeviceProof` the client sends along
+    let mut login = |device: Option<&auth::TrustedDevice>| {
+        let (proof, auth) = client_login_with(&mut srp, 1, &user, "password", name.clone());
+        let device = device.map(|device| device.proof(&proof.unverified_key()).unwrap());
+        (proof, auth.unwrap(), device)
+    };
+
+    // no trusted device yet, so the code is asked for
+    let (proof, auth, _) = login(None);
+    let resp = encode(&totp_logins.challenge(auth, user.totp(&[2; 32]).unwrap().unwrap(), 1, 1030)).unwrap();
+    let auth = totp_logins.verify(decode_message(&proof.totp_request(&resp, totp.code(1000)).unwrap()).unwrap(), 1, 1000).unwrap();
+    let key = proof.finish(&encode(&auth.response()).unwrap()).unwrap();
+    devices.trust(&auth, &name, "laptop", 1000).await;
+    let laptop = auth::TrustedDevice::new(&key);
+
+    // from the trusted device the `AuthResponse` goes out right away
+    let (proof, auth, device) = login(Some(&laptop));
+    let device: DeviceProof = decode_message(device.as_ref().unwrap()).unwrap();
+    assert_eq!(devices.check(&auth, &name, &device, 1010).await, Ok(DeviceId(laptop.id())));
+    assert_eq!(proof.finish(&encode(&auth.response()).unwrap()).unwrap(), auth.get_key());
+    // but its proof does not carry over to another login
+    let (_, auth, _) = login(None);
+    assert_eq!(devices.check(&auth, &name, &device, 1020).await, Err(DeviceError::Invalid));
+}
@@ -1,27 +1,133 @@
-use auth_common::AuthResponse;
-pub use auth_common::{AuthReq, Data, PreAuthReq, PreAuthResp, RegisterReq};
-use blake2::Blake2b512;
+pub use auth_common::{
+    AuthReq, AuthResponse, Data, ErrorCode, ErrorResp, Frame, FrameError, HandshakeError, Message,
+    MessageType, PreAuthReq, PreAuthResp, RegisterReq, Suite, Suites, decode_message,
+};
+use auth_common::{ServerChallenge, ServerDone};
+use blake2::{Blake2b512, Digest};
 use gxhash::{GxBuildHasher, GxHasher, HashMap};
 use rand::{random, Rng, RngCore};
 use serde::{Deserialize, Serialize};
-use srp::{server::SrpServer4096, Encoding, Uint, U4096};
+use srp::{Encoding, Uint, U4096};
 use std::{hash::Hash, time::Instant};
 pub use srp::SrpAuthError;
 
-#[derive(Serialize, Deserialize)]
+pub use auth_common::channel;
+pub mod device;
+pub mod limit;
+pub mod recovery;
+pub mod resume;
+pub mod session;
+pub mod signing;
+pub mod totp;
+#[cfg(feature = "codec")]
+pub mod codec;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+#[cfg(feature = "jwt")]
+pub mod token;
+#[cfg(feature = "axum")]
+pub mod router;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UserData {
     #[serde(with = "serdapt_base64::StdBase64Array")]
     salt: [u8; 32],
     #[serde(with = "serdapt_base64::StdBase64Array")]
     v: [u8; 512],
+    /// Records stored before suites existed have no tag and use the default suite.
+    #[serde(default)]
+    suite: Suite,
+    /// Second factor, sealed with a key of the server. See `totp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    totp: Option<totp::SealedTotp>,
+    /// Hashes of the unused recovery codes. See `recovery`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recovery: Option<recovery::RecoveryCodes>,
+    /// Verifier of the recovery key. See `recovery`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recovery_key: Option<recovery::RecoveryVerifier>,
 }
 
-type Digest = Blake2b512;
+impl UserData {
+    /// Stand-in record for a username that does not exist.
+    ///
+    /// Run `SrpAuth::pre_auth` with this instead of rejecting the request, so an
+    /// unknown user fails at `SrpAuth::auth` exactly like a wrong password.
+    /// The salt and the suite are derived from `secret` and the username, so
+    /// they stay the same across requests just like real ones would. A suite
+    /// the client does not offer is replaced the same way as for a real user,
+    /// see `auth_common::ServerChallenge::new`.
+    ///
+    /// Logins with the recovery key take `recovery::RecoveryLogin::decoy`.
+    pub fn decoy(username: &str, secret: &[u8; 32]) -> UserData {
+        UserData::decoy_with(b"", username, secret)
+    }
+    /// `decoy` with its own salts for each `label`.
+    fn decoy_with(label: &[u8], username: &str, secret: &[u8; 32]) -> UserData {
+        let hash = Blake2b512::new()
+            .chain_update(label)
+            .chain_update(secret)
+            .chain_update(username.as_bytes())
+            .finalize();
+        let mut salt = [0; 32];
+        salt.copy_from_slice(&hash[..32]);
+        let suite = Suite::ALL[hash[32] as usize % Suite::ALL.len()];
+        let v = rand_num::<{ U4096::LIMBS }>().to_le_bytes();
+        UserData { salt, v, suite, totp: None, recovery: None, recovery_key: None }
+    }
+    pub fn suite(&self) -> Suite {
+        self.suite
+    }
+    /// Enroll the user in TOTP, or remove it with `None`. The secret is sealed
+    /// under `key`, which stays with the server.
+    pub fn set_totp(&mut self, totp: Option<&totp::Totp>, key: &[u8; 32]) {
+        self.totp = totp.map(|totp| totp::SealedTotp::seal(totp, key, &self.salt));
+    }
+    pub fn has_totp(&self) -> bool {
+        self.totp.is_some()
+    }
+    /// The TOTP of the user, if enrolled.
+    pub fn totp(&self, key: &[u8; 32]) -> Result<Option<totp::Totp>, totp::TotpError> {
+        self.totp.as_ref().map(|sealed| sealed.open(key, &self.salt)).transpose()
+    }
+    /// Give the user new recovery codes, replacing any old ones, or remove them
+    /// with `None`.
+    pub fn set_recovery_codes(&mut self, codes: Option<recovery::RecoveryCodes>) {
+        self.recovery = codes;
+    }
+    pub fn recovery_codes(&self) -> Option<&recovery::RecoveryCodes> {
+        self.recovery.as_ref()
+    }
+    /// Give the user a recovery key, replacing any old one, or remove it with
+    /// `None`.
+    pub fn set_recovery_key(&mut self, key: Option<recovery::RecoveryVerifier>) {
+        self.recovery_key = key;
+    }
+    /// The record for `SrpAuth::recovery_pre_auth`, `None` if the user has no
+    /// recovery key. Use `RecoveryLogin::decoy` then, as for unknown users.
+    pub fn recovery_login(&self) -> Option<recovery::RecoveryLogin> {
+        self.recovery_key.as_ref().map(recovery::RecoveryVerifier::login)
+    }
+}
 
 pub trait CredHasher {
     fn get_user_data(&self, username: &str) -> impl Future<Output=Option<UserData>>;
 }
 
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut out = [0; N];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
 fn rand_num<const N: usize>() -> Uint<N> {
     let mut buf = [0; N];
     rand::thread_rng().fill(buf.as_mut_slice());
@@ -39,12 +145,24 @@ impl<K: Eq + Hash + Clone, D> Default for SrpAuth<K, D> {
 }
 
 pub struct Authenticated<D> {
-    shared_key: U4096,
+    done: ServerDone,
     pub data: D
 }
 impl<D> Authenticated<D> {
     pub fn get_key(&self) -> [u8; 512] {
-        self.shared_key.to_le_bytes()
+        self.done.key()
+    }
+    /// The server proof, for the client to check that the server knows the verifier.
+    pub fn response(&self) -> AuthResponse {
+        self.done.message()
+    }
+    /// Encrypted channel keyed from the shared key.
+    pub fn channel(&self) -> channel::Channel {
+        channel::Channel::new(&self.get_key(), channel::Role::Server)
+    }
+    /// Verifier for the requests the client signs after the login.
+    pub fn request_verifier(&self) -> signing::Verifier {
+        signing::Verifier::new(&self.get_key())
     }
 }
 
@@ -52,29 +170,35 @@ impl<K: Hash + Eq + Clone + Unpin, D: Unpin> SrpAuth<K, D> {
     pub fn new() -> Self {
         SrpAuth { logins: HashMap::with_hasher(GxBuildHasher::default()) }
     }
-    pub fn pre_auth(&mut self, req: PreAuthReq, user_data: &UserData, key1: K, data: D, expires: Instant) -> Result<PreAuthResp, ()> {
-        let b = rand_num();
-        let server = SrpServer4096::<Digest>::new(b);
-
-        let salt = user_data.salt;
-        let v = U4096::from_le_bytes(user_data.v);
-        let a_pub = U4096::from_le_bytes(req.a_pub.into());
-        let b_pub = server.compute_public_ephemeral(&v).to_le_bytes().into();
-
-        let key2 = random();
+    /// Start a login. Fails only if the client offers no suite the server implements,
+    /// see `auth_common::ServerChallenge::new`.
+    ///
+    /// The response borrows `B` from the pending login, encode it right away.
+    pub fn pre_auth(&mut self, req: PreAuthReq, user_data: &UserData, key1: K, data: D, expires: Instant) -> Result<PreAuthResp<'_>, AuthError> {
+        let step1 = Step1::new(&req, user_data, data, expires)?;
+        Ok(self.insert(key1, step1))
+    }
+    /// Store a login started with `Step1::new`, the second half of `pre_auth`.
+    pub fn insert(&mut self, key1: K, step1: Step1<D>) -> PreAuthResp<'_> {
+        let key2 = u64::from_le_bytes(step1.challenge.message().key);
+        let step1 = self.logins.entry((key1, key2))
+            .insert_entry(step1)
+            .into_mut();
 
-        self.logins.insert((key1, key2), Step1 { server, v, a_pub, data, expires });
-        
-        Ok(PreAuthResp { salt, b_pub, key: key2.to_le_bytes() })
+        step1.challenge.message()
     }
     pub fn auth(&mut self, req: AuthReq, key1: K, now: Instant) -> Result<Authenticated<D>, AuthError> {
+        self.take(&req, key1, now)?.step2(req)
+    }
+    /// Remove the login `req` answers, the first half of `auth`. Finish it
+    /// with `Step1::step2`.
+    pub fn take(&mut self, req: &AuthReq, key1: K, now: Instant) -> Result<Step1<D>, AuthError> {
         let key2 = u64::from_le_bytes(req.key);
         let step1 = self.logins.remove(&(key1, key2)).ok_or(AuthError::KeyNotFound)?;
         if step1.expires < now {
             return Err(AuthError::Expired);
         }
-        let auth = step1.step2(req).map_err(AuthError::Srp)?;
-        Ok(auth)
+        Ok(step1)
     }
     pub fn clean(&mut self, now: Instant) {
         self.logins.retain(|_, s| s.expires >= now);
@@ -83,7 +207,8 @@ impl<K: Hash + Eq + Clone + Unpin, D: Unpin> SrpAuth<K, D> {
 pub enum AuthError {
     Srp(SrpAuthError),
     KeyNotFound,
-    Expired
+    Expired,
+    UnsupportedSuite,
 }
 impl AuthError {
     pub fn message(&self) -> &'static str {
@@ -91,7 +216,28 @@ impl AuthError {
             AuthError::Srp(SrpAuthError::BadRecordMac) => "bad record mac",
             AuthError::Srp(SrpAuthError::IllegalParameter) => "illegal parameter",
             AuthError::Expired => "expired",
-            AuthError::KeyNotFound => "key not found"
+            AuthError::KeyNotFound => "key not found",
+            AuthError::UnsupportedSuite => "unsupported suite",
+        }
+    }
+    /// The code to report to the client. Does not reveal which check failed.
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            AuthError::Srp(_) => ErrorCode::AuthFailed,
+            AuthError::Expired | AuthError::KeyNotFound => ErrorCode::HandshakeExpired,
+            AuthError::UnsupportedSuite => ErrorCode::UnsupportedSuite,
+        }
+    }
+    pub fn response(&self) -> ErrorResp {
+        ErrorResp::new(self.code())
+    }
+}
+impl From<HandshakeError> for AuthError {
+    fn from(e: HandshakeError) -> Self {
+        match e {
+            HandshakeError::UnsupportedSuite => AuthError::UnsupportedSuite,
+            HandshakeError::IllegalParameter => AuthError::Srp(SrpAuthError::IllegalParameter),
+            HandshakeError::BadProof => AuthError::Srp(SrpAuthError::BadRecordMac),
         }
     }
 }
@@ -103,30 +249,46 @@ impl std::fmt::Debug for AuthError {
 
 
 pub struct Step1<D> {
-    server: SrpServer4096::<Blake2b512>,
-    v: U4096,
-    a_pub: U4096,
+    challenge: ServerChallenge,
     data: D,
     expires: Instant,
+    /// Started by `SrpAuth::recovery_pre_auth`, see `recovery`.
+    recovery: bool,
 }
 impl<D> Step1<D> {
-    pub fn step2(self, req: AuthReq) -> Result<Authenticated<D>, SrpAuthError> {
-        let verifier = self.server.process_reply(&self.v, &self.a_pub)?;
-        verifier.verify_client(&req.proof)?;
-        Ok(Authenticated { shared_key: *verifier.key(), data: self.data })
+    /// A login as `SrpAuth::pre_auth` starts it, without storing it.
+    ///
+    /// `new` and `step2` do the 4096-bit arithmetic of the login. A server
+    /// that shares one `SrpAuth` between requests can run them outside its
+    /// lock, with `SrpAuth::insert` and `SrpAuth::take` under it.
+    pub fn new(req: &PreAuthReq, user_data: &UserData, data: D, expires: Instant) -> Result<Step1<D>, AuthError> {
+        Step1::start(req, user_data, data, expires, false)
+    }
+    fn start(req: &PreAuthReq, user_data: &UserData, data: D, expires: Instant, recovery: bool) -> Result<Step1<D>, AuthError> {
+        let b = rand_num::<{ U4096::LIMBS }>().to_le_bytes();
+        let key2: u64 = random();
+        let challenge = ServerChallenge::new(req, user_data.salt, &user_data.v, user_data.suite, b, key2.to_le_bytes())?;
+        Ok(Step1 { challenge, data, expires, recovery })
+    }
+    /// Fails for a login with the recovery key, see `recovery_step2`.
+    pub fn step2(self, req: AuthReq) -> Result<Authenticated<D>, AuthError> {
+        if self.recovery {
+            return Err(AuthError::KeyNotFound);
+        }
+        let done = self.challenge.process(&req)?;
+        Ok(Authenticated { done, data: self.data })
     }
 }
 
 pub fn decode_register_req<'a>(req: &'a [u8]) -> Option<(&'a str, UserData)> {
-    use auth_common::Data;
-
-    let (req, _) = RegisterReq::decode(req)?;
-    Some((req.username, UserData { salt: req.salt, v: req.verifier }))
+    let req: RegisterReq = decode_message(req).ok()?;
+    Some((req.username, UserData { salt: req.salt, v: *req.verifier, suite: req.suite, totp: None, recovery: None, recovery_key: None }))
 }
 
-pub fn encode<D: for <'a> Data<'a>>(val: &D) -> Option<Vec<u8>> {
-    let mut buf = vec![0; D::SIZE];
-    let encoded = val.encode(&mut buf)?;
+/// Encode `val` as a framed message.
+pub fn encode<'a, M: Message<'a>>(val: &M) -> Option<Vec<u8>> {
+    let mut buf = vec![0; M::FRAME_SIZE];
+    let encoded = auth_common::encode_message(val, &mut buf)?;
     let len = encoded.len();
     buf.truncate(len);
     Some(buf)
@@ -134,10 +296,159 @@ pub fn encode<D: for <'a> Data<'a>>(val: &D) -> Option<Vec<u8>> {
 
 #[test]
 fn test_encode() {
+    let b_pub = random();
     let buf = encode(&PreAuthResp {
-        b_pub: random(),
+        b_pub: &b_pub,
         key: random(),
-        salt: random()
+        salt: random(),
+        suite: Suite::Blake2sArgon2id,
     }).unwrap();
-    assert_eq!(buf.len(), 512 + 8 + 32);
+    assert_eq!(buf.len(), 4 + 512 + 8 + 32 + 1);
+    assert!(decode_message::<PreAuthResp>(&buf).is_ok());
+    assert_eq!(decode_message::<AuthReq>(&buf).err(), Some(FrameError::UnexpectedType(MessageType::PreAuthResp)));
+}
+
+#[test]
+fn test_suite_negotiation() {
+    let mut auth = SrpAuth::<u32, ()>::new();
+    let user = UserData { salt: random(), v: random(), suite: Suite::Blake2sArgon2id, totp: None, recovery: None, recovery_key: None };
+    let a_pub = rand_num::<{ U4096::LIMBS }>().to_le_bytes();
+    let expires = Instant::now();
+
+    let req = PreAuthReq { a_pub: &a_pub, username: "user", suites: Suites::NONE };
+    let err = auth.pre_auth(req, &user, 1, (), expires).err().unwrap();
+    assert_eq!(err.code(), ErrorCode::UnsupportedSuite);
+
+    // a user of a suite the client does not offer gets one it does, like a decoy
+    let req = PreAuthReq { a_pub: &a_pub, username: "user", suites: Suites::NONE.with(Suite::Blake2bArgon2d) };
+    let resp = auth.pre_auth(req, &user, 2, (), expires).unwrap();
+    assert_eq!((resp.suite, resp.salt), (Suite::Blake2bArgon2d, user.salt));
+
+    let req = PreAuthReq { a_pub: &a_pub, username: "user", suites: Suites::all() };
+    let resp = auth.pre_auth(req, &user, 1, (), expires).unwrap();
+    assert_eq!(resp.suite, Suite::Blake2sArgon2id);
+}
+
+#[test]
+fn test_decoy_suite() {
+    let secret = [7; 32];
+    let suite_of = |i: usize| UserData::decoy(&format!("user{i}"), &secret).suite;
+    assert_eq!(suite_of(0), suite_of(0));
+
+    // decoys spread over the suites, so a non-default one does not prove the user exists
+    let i = (0..).find(|&i| suite_of(i) == Suite::Blake2sArgon2id).unwrap();
+    assert!((0..).any(|i| suite_of(i) == Suite::default()));
+    let mut auth = SrpAuth::<u32, ()>::new();
+    let a_pub = rand_num::<{ U4096::LIMBS }>().to_le_bytes();
+    let name = format!("user{i}");
+    let req = PreAuthReq { a_pub: &a_pub, username: &name, suites: Suites::all() };
+    let resp = auth.pre_auth(req, &UserData::decoy(&name, &secret), 1, (), Instant::now()).unwrap();
+    assert_eq!(resp.suite, Suite::Blake2sArgon2id);
+}
+
+#[test]
+fn test_frame_version() {
+    let mut buf = encode(&AuthResponse { proof: [1; 64] }).unwrap();
+    assert!(decode_message::<AuthResponse>(&buf[..buf.len() - 1]).is_err());
+    buf[0] = 2;
+    let err = decode_message::<AuthResponse>(&buf).err().unwrap();
+    assert_eq!(err, FrameError::UnsupportedVersion(2));
+    assert_eq!(err.code(), ErrorCode::UnsupportedVersion);
+}
+
+#[test]
+fn test_error_resp() {
+    let buf = encode(&ErrorResp::new(ErrorCode::TooManyAttempts).with_retry_after(30)).unwrap();
+    let resp: ErrorResp = decode_message(&buf).unwrap();
+    assert_eq!(resp.code, ErrorCode::TooManyAttempts);
+    assert_eq!(resp.retry_after, Some(30));
+
+    assert_eq!(AuthError::KeyNotFound.code(), AuthError::Expired.code());
+    assert!(ErrorResp::decode(&[0, 0]).is_none());
+}
+
+#[cfg(test)]
+impl UserData {
+    /// The user "user" with `password`, of the default suite.
+    pub(crate) fn test_user(password: &str) -> UserData {
+        let salt: [u8; 32] = random();
+        let v = Suite::default().compute_verifier(b"user", password.as_bytes(), &salt);
+        UserData { salt, v, suite: Suite::default(), totp: None, recovery: None, recovery_key: None }
+    }
+}
+
+/// Log in as "user" with the client crate, up to `SrpAuth::auth`. The
+/// client is left waiting for the `AuthResponse`.
+#[cfg(test)]
+fn client_login_with<K: Hash + Eq + Clone + Unpin, D: Unpin>(
+    srp: &mut SrpAuth<K, D>,
+    key1: K,
+    user: &UserData,
+    password: &str,
+    data: D,
+) -> (auth::Proof, Result<Authenticated<D>, AuthError>) {
+    use std::time::Duration;
+
+    let login = auth::Login::from_secret(random());
+    let req = login.request("user").unwrap();
+    let resp = srp.pre_auth(decode_message(&req).unwrap(), user, key1.clone(), data, Instant::now() + Duration::from_secs(10)).unwrap();
+    let proof = login.process(&encode(&resp).unwrap(), "user", password).unwrap();
+    let req = proof.request().unwrap();
+    let srv = srp.auth(decode_message(&req).unwrap(), key1, Instant::now());
+    (proof, srv)
+}
+
+/// Log in with the client crate, both ends get the same key.
+#[cfg(test)]
+fn client_login() -> (Authenticated<()>, [u8; 512]) {
+    let (proof, srv) = client_login_with(&mut SrpAuth::new(), 1, &UserData::test_user("password"), "password", ());
+    let srv = srv.unwrap();
+    let key = proof.finish(&encode(&srv.response()).unwrap()).unwrap();
+    (srv, key)
+}
+
+#[test]
+fn test_channel() {
+    let (srv, key) = client_login();
+    let mut client = auth::Channel::new(&key, auth::Role::Client);
+    let mut server = srv.channel();
+    let record = client.seal_to_vec(b"request").unwrap();
+    assert_eq!(server.open_to_vec(&record).unwrap(), b"request");
+    assert_eq!(server.open_to_vec(&record), Err(channel::ChannelError::Replayed));
+    let record = server.seal_to_vec(b"response").unwrap();
+    assert_eq!(client.open_to_vec(&record).unwrap(), b"response");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_after_login() {
+    let (srv, key) = client_login();
+
+    // requests signed by the client check out on the server
+    let sig = auth::Signer::new(&key).sign(&mut rand::rng(), "GET", "/me", b"", 1000);
+    assert_eq!(srv.request_verifier().verify("GET", "/me", b"", &sig, 1000), Ok(()));
+
+    // resume with the ticket, then with the next one
+    let mut tickets = resume::Tickets::new(&random());
+    let ticket = tickets.issue(&srv, 1000).unwrap();
+    let resumption = auth::Resumption::new(&key, &encode(&ticket.message()).unwrap()).unwrap();
+    let blob = resumption.export(&mut rand::rng(), &[9; 32]);
+    let resuming = auth::Resumption::import(&blob, &[9; 32], 1000).unwrap().start(&mut rand::rng());
+    let req = resuming.request().unwrap();
+    let resumed: resume::Resumed<()> = tickets.resume(decode_message(&req).unwrap(), 1010).unwrap();
+    let (key, resumption) = resuming.finish(&encode(&resumed.response()).unwrap()).unwrap();
+    assert_eq!(key, resumed.get_key());
+    assert_eq!(tickets.resume::<()>(decode_message(&req).unwrap(), 1020).err(), Some(resume::TicketError::Reused));
+
+    let resuming = resumption.start(&mut rand::rng());
+    let resumed: resume::Resumed<()> = tickets.resume(decode_message(&resuming.request().unwrap()).unwrap(), 1020).unwrap();
+    assert_eq!(resuming.finish(&encode(&resumed.response()).unwrap()).unwrap().0, resumed.get_key());
+
+    // the client derives the device the server trusts
+    let devices = device::Devices::new(device::MemoryDevices::default());
+    let id = devices.trust(&srv, &"user".to_owned(), "test", 1000).await;
+    let trusted = auth::TrustedDevice::new(&srv.get_key());
+    assert_eq!(&trusted.id(), id.as_bytes());
+    let proof = trusted.proof(&srv.get_key()).unwrap();
+    assert_eq!(devices.check(&srv, &"user".to_owned(), &decode_message(&proof).unwrap(), 1010).await, Ok(id));
 }
@@ -0,0 +1,80 @@
+//! A limit on login attempts.
+//!
+//! `Attempts` counts the attempts of each key, usually the username, and
+//! refuses more than `max` of them within a window. Count at the start of
+//! each login, before the user is looked up, so unknown users are limited
+//! exactly like real ones, and `reset` the count once a login succeeds.
+//! Times are seconds since the Unix epoch.
+//!
+//! A limit per username also lets anyone lock a user out for a window, so
+//! keep `max` generous and put a limit per address in front of it.
+use std::{collections::HashMap, hash::Hash};
+
+use auth_common::{ErrorCode, ErrorResp};
+
+/// `max` attempts were made within the window.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TooManyAttempts {
+    /// Seconds until the key may try again.
+    pub retry_after: u64,
+}
+impl TooManyAttempts {
+    pub fn response(self) -> ErrorResp {
+        let secs = self.retry_after.try_into().unwrap_or(u32::MAX);
+        ErrorResp::new(ErrorCode::TooManyAttempts).with_retry_after(secs)
+    }
+}
+
+/// Attempts of each key within the current window.
+pub struct Attempts<K> {
+    /// `(start of the window, attempts)` of each key.
+    counts: HashMap<K, (u64, u32)>,
+    max: u32,
+    window: u64,
+}
+impl<K: Eq + Hash> Attempts<K> {
+    /// Allow `max` attempts per key within `window` seconds.
+    pub fn new(max: u32, window: u64) -> Self {
+        Attempts { counts: HashMap::new(), max, window }
+    }
+    /// Count an attempt of `key`, unless it used up its attempts already.
+    pub fn attempt(&mut self, key: K, now: u64) -> Result<(), TooManyAttempts> {
+        self.clean(now);
+        let (start, count) = self.counts.entry(key).or_insert((now, 0));
+        if *count >= self.max {
+            return Err(TooManyAttempts { retry_after: *start + self.window - now });
+        }
+        *count += 1;
+        Ok(())
+    }
+    /// Forget the attempts of `key`, e.g. after a successful login.
+    pub fn reset(&mut self, key: &K) {
+        self.counts.remove(key);
+    }
+    /// Drop the counts of windows that are over.
+    pub fn clean(&mut self, now: u64) {
+        let window = self.window;
+        self.counts.retain(|_, (start, _)| now < start.saturating_add(window));
+    }
+}
+
+#[test]
+fn test_attempts() {
+    let mut attempts = Attempts::new(2, 60);
+    assert_eq!(attempts.attempt("user", 1000), Ok(()));
+    assert_eq!(attempts.attempt("user", 1010), Ok(()));
+    assert_eq!(attempts.attempt("user", 1020), Err(TooManyAttempts { retry_after: 40 }));
+    assert_eq!(attempts.attempt("other", 1020), Ok(()));
+    // the window starts with the first attempt
+    assert_eq!(attempts.attempt("user", 1060), Ok(()));
+    assert_eq!(attempts.attempt("user", 1070), Ok(()));
+
+    assert_eq!(attempts.attempt("user", 1070), Err(TooManyAttempts { retry_after: 50 }));
+    attempts.reset(&"user");
+    assert_eq!(attempts.attempt("user", 1070), Ok(()));
+    attempts.clean(2000);
+    assert!(attempts.counts.is_empty());
+
+    let resp = TooManyAttempts { retry_after: 30 }.response();
+    assert_eq!((resp.code, resp.retry_after), (ErrorCode::TooManyAttempts, Some(30)));
+}
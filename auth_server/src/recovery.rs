@@ -0,0 +1,267 @@
+//! Recovery codes and recovery keys, for a user who lost the password or the
+//! second factor.
+//!
+//! # Recovery codes
+//!
+//! Generate the codes at enrollment with `RecoveryCodes::generate`, show them
+//! to the user once and put the hashes into the record with
+//! `UserData::set_recovery_codes`. `auth_utils --recovery-codes` does the same
+//! offline. To reset the password, the client sends a `RecoverReq`: one of
+//! the codes and a new `RegisterReq` for the same username. `recover` checks
+//! the code and returns the new record. Store it in place of the old one and
+//! log the user out everywhere, e.g. with `Sessions::revoke_user` and
+//! `Devices::revoke_all`. Resumption tickets survive a reset and keep logging
+//! in until they expire, end them with `Tickets::revoke_user`.
+//!
+//! A code is 80 random bits, see `auth_common::recovery` for the format. Still
+//! count each `recover` against the username in `limit::Attempts`, like logins.
+//!
+//! The `RecoverReq` is not encrypted, there is no login to key a `Channel`
+//! from. The code and the verifier of the new password go as they are, and
+//! with the verifier an eavesdropper can guess the password offline. Only
+//! accept it over TLS. The reset with a recovery key below seals its request.
+//!
+//! # Recovery keys
+//!
+//! A recovery key is a second SRP credential of the user, so the server never
+//! sees it either. `auth_utils --recovery-key` prints a key and a `RegisterReq`
+//! with its verifier, which goes into the record with
+//! `RecoveryVerifier::from_register` and `UserData::set_recovery_key`. The
+//! client logs in with `RecoveryKey::as_str` as the password, on endpoints of
+//! its own that run `SrpAuth::recovery_pre_auth` with
+//! `UserData::recovery_login` and `SrpAuth::recovery_auth`. For unknown users
+//! and users without a key, always use `RecoveryLogin::decoy`, not
+//! `UserData::decoy`. The login ends in a `ResetGrant`, not `Authenticated`:
+//! it grants no session, token, ticket or device, only a password reset. The
+//! client seals the new `RegisterReq` with a `Channel` keyed from that login,
+//! and `reset` opens it and returns the new record.
+pub use auth_common::RecoverReq;
+pub use auth_common::recovery::{RecoveryCode, RecoveryCodes, RecoveryKey};
+use std::{hash::Hash, time::Instant};
+
+use auth_common::{
+    AuthReq, AuthResponse, ErrorCode, PreAuthReq, PreAuthResp, RegisterReq, ServerDone, Suite,
+    channel::{Channel, Role},
+    decode_message,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{AuthError, SrpAuth, Step1, UserData};
+
+/// The SRP verifier of a recovery key.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecoveryVerifier {
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    salt: [u8; 32],
+    #[serde(with = "serdapt_base64::StdBase64Array")]
+    v: [u8; 512],
+    suite: Suite,
+}
+impl RecoveryVerifier {
+    /// The verifier of a `RegisterReq` made with the key as the password.
+    pub fn from_register(req: &RegisterReq) -> RecoveryVerifier {
+        RecoveryVerifier { salt: req.salt, v: *req.verifier, suite: req.suite }
+    }
+    pub(crate) fn login(&self) -> RecoveryLogin {
+        RecoveryLogin(UserData { salt: self.salt, v: self.v, suite: self.suite, totp: None, recovery: None, recovery_key: None })
+    }
+}
+/// Does not print the verifier.
+impl std::fmt::Debug for RecoveryVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecoveryVerifier").field("suite", &self.suite).finish_non_exhaustive()
+    }
+}
+
+/// The record for a login with the recovery key, see `UserData::recovery_login`.
+pub struct RecoveryLogin(UserData);
+impl RecoveryLogin {
+    /// Stand-in for an unknown user or a user without a recovery key.
+    ///
+    /// Use this one, not `UserData::decoy`: its salts differ from those of
+    /// the password decoys. With the same salt on both logins, unknown users
+    /// would stand out from users with a key.
+    pub fn decoy(username: &str, secret: &[u8; 32]) -> RecoveryLogin {
+        RecoveryLogin(UserData::decoy_with(b"auth recovery decoy", username, secret))
+    }
+}
+
+/// A login with the recovery key. Only `reset` takes it.
+pub struct ResetGrant<D> {
+    done: ServerDone,
+    pub data: D,
+}
+impl<D> ResetGrant<D> {
+    /// The server proof, for the client to check that the server knows the verifier.
+    pub fn response(&self) -> AuthResponse {
+        self.done.message()
+    }
+}
+
+impl<D> Step1<D> {
+    /// `Step1::new` for a login with the recovery key.
+    pub fn recovery(req: &PreAuthReq, login: &RecoveryLogin, data: D, expires: Instant) -> Result<Step1<D>, AuthError> {
+        Step1::start(req, &login.0, data, expires, true)
+    }
+    /// `step2` for a login with the recovery key. Fails for any other login.
+    pub fn recovery_step2(self, req: AuthReq) -> Result<ResetGrant<D>, AuthError> {
+        if !self.recovery {
+            return Err(AuthError::KeyNotFound);
+        }
+        let done = self.challenge.process(&req)?;
+        Ok(ResetGrant { done, data: self.data })
+    }
+}
+
+impl<K: Hash + Eq + Clone + Unpin, D: Unpin> SrpAuth<K, D> {
+    /// Start a login with the recovery key. Only `recovery_auth` finishes it.
+    pub fn recovery_pre_auth(&mut self, req: PreAuthReq, login: &RecoveryLogin, key1: K, data: D, expires: Instant) -> Result<PreAuthResp<'_>, AuthError> {
+        let step1 = Step1::recovery(&req, login, data, expires)?;
+        Ok(self.insert(key1, step1))
+    }
+    /// Finish a login started with `recovery_pre_auth`.
+    pub fn recovery_auth(&mut self, req: AuthReq, key1: K, now: Instant) -> Result<ResetGrant<D>, AuthError> {
+        self.take(&req, key1, now)?.recovery_step2(req)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecoveryError {
+    /// The user has no recovery codes.
+    NoCodes,
+    /// Not one of the remaining codes of the user.
+    Invalid,
+    /// The sealed `RegisterReq` does not open or decode.
+    Malformed,
+    /// The `RegisterReq` is for another user.
+    WrongUser,
+}
+impl RecoveryError {
+    pub fn code(self) -> ErrorCode {
+        match self {
+            RecoveryError::NoCodes | RecoveryError::Invalid | RecoveryError::WrongUser => ErrorCode::AuthFailed,
+            RecoveryError::Malformed => ErrorCode::Malformed,
+        }
+    }
+}
+
+/// Check the code of `req` against `user`, the current record of `username`,
+/// and return the record with the new password.
+///
+/// The code is used up, the other codes carry over. TOTP does not: the user
+/// may have lost it, so they have to enroll again. `req` arrives in the
+/// clear, take it over TLS only, see the module documentation.
+pub fn recover(req: &RecoverReq, username: &str, user: &UserData) -> Result<UserData, RecoveryError> {
+    if req.register.username != username {
+        return Err(RecoveryError::WrongUser);
+    }
+    let mut codes = user.recovery.clone().ok_or(RecoveryError::NoCodes)?;
+    if !codes.redeem(req.code) {
+        return Err(RecoveryError::Invalid);
+    }
+    Ok(user.with_password(&req.register, Some(codes)))
+}
+
+/// Open the `RegisterReq` the client sealed after `grant`, the login with the
+/// recovery key of `username`, and return the record with the new password.
+/// `user` is the current record.
+///
+/// The recovery key and codes carry over, TOTP does not.
+pub fn reset<D>(grant: &ResetGrant<D>, sealed: &[u8], username: &str, user: &UserData) -> Result<UserData, RecoveryError> {
+    let plain = Channel::new(&grant.done.key(), Role::Server).open_to_vec(sealed).map_err(|_| RecoveryError::Malformed)?;
+    let req: RegisterReq = decode_message(&plain).map_err(|_| RecoveryError::Malformed)?;
+    if req.username != username {
+        return Err(RecoveryError::WrongUser);
+    }
+    Ok(user.with_password(&req, user.recovery.clone()))
+}
+
+impl UserData {
+    /// The record after a reset to the password of `req`.
+    fn with_password(&self, req: &RegisterReq, recovery: Option<RecoveryCodes>) -> UserData {
+        UserData {
+            salt: req.salt,
+            v: *req.verifier,
+            suite: req.suite,
+            totp: None,
+            recovery,
+            recovery_key: self.recovery_key.clone(),
+        }
+    }
+}
+
+#[test]
+fn test_recover() {
+    let (codes, hashes) = RecoveryCodes::generate(&mut rand::rng(), 3);
+    assert_eq!(hashes.remaining(), 3);
+    let mut user = UserData::test_user("password");
+    let verifier = [3; 512];
+    let req = |code| RecoverReq {
+        code,
+        register: RegisterReq { username: "user", salt: [4; 32], verifier: &verifier, suite: Suite::default() },
+    };
+    assert_eq!(recover(&req(codes[0].as_str()), "user", &user).err(), Some(RecoveryError::NoCodes));
+
+    user.set_recovery_codes(Some(hashes));
+    assert_eq!(recover(&req(codes[1].as_str()), "other", &user).err(), Some(RecoveryError::WrongUser));
+    let user = recover(&req(codes[1].as_str()), "user", &user).unwrap();
+    assert_eq!((user.salt, user.v), ([4; 32], [3; 512]));
+    assert_eq!(user.recovery_codes().map(RecoveryCodes::remaining), Some(2));
+    // single use, and typed by hand
+    assert_eq!(recover(&req(codes[1].as_str()), "user", &user).err(), Some(RecoveryError::Invalid));
+    let typed = codes[2].as_str().to_uppercase().replace('-', " ");
+    assert!(recover(&req(&typed), "user", &user).is_ok());
+    assert_eq!(recover(&req("not a code"), "user", &user).err(), Some(RecoveryError::Invalid));
+}
+
+#[test]
+fn test_reset() {
+    use rand::random;
+    use std::time::{Duration, Instant};
+
+    let mut user = UserData::test_user("password");
+    assert!(user.recovery_login().is_none());
+    let key = RecoveryKey::from_bytes(random());
+    let register = auth::register(&mut rand::rng(), "user", key.as_str(), Suite::default()).unwrap();
+    user.set_recovery_key(Some(RecoveryVerifier::from_register(&decode_message(&register).unwrap())));
+
+    // log in with the key, not the password
+    let login = |password: &str| {
+        let mut srp = SrpAuth::<u32, ()>::new();
+        let login = auth::Login::from_secret(random());
+        let req = login.request("user").unwrap();
+        let resp = srp.recovery_pre_auth(decode_message(&req).unwrap(), &user.recovery_login().unwrap(), 1, (), Instant::now() + Duration::from_secs(10)).unwrap();
+        let proof = login.process(&crate::encode(&resp).unwrap(), "user", password).unwrap();
+        let srv = srp.recovery_auth(decode_message(&proof.request().unwrap()).unwrap(), 1, Instant::now()).ok()?;
+        Some((proof.finish(&crate::encode(&srv.response()).unwrap()).unwrap(), srv))
+    };
+    assert!(login("password").is_none());
+
+    // the two kinds of login do not finish each other
+    let mut srp = SrpAuth::<u32, ()>::new();
+    let expires = Instant::now() + Duration::from_secs(10);
+    let client = auth::Login::from_secret(random());
+    let resp = srp.recovery_pre_auth(decode_message(&client.request("user").unwrap()).unwrap(), &user.recovery_login().unwrap(), 1, (), expires).unwrap();
+    let proof = client.process(&crate::encode(&resp).unwrap(), "user", key.as_str()).unwrap();
+    assert!(matches!(srp.auth(decode_message(&proof.request().unwrap()).unwrap(), 1, Instant::now()), Err(AuthError::KeyNotFound)));
+    let client = auth::Login::from_secret(random());
+    let resp = srp.pre_auth(decode_message(&client.request("user").unwrap()).unwrap(), &user, 1, (), expires).unwrap();
+    let proof = client.process(&crate::encode(&resp).unwrap(), "user", "password").unwrap();
+    assert!(matches!(srp.recovery_auth(decode_message(&proof.request().unwrap()).unwrap(), 1, Instant::now()), Err(AuthError::KeyNotFound)));
+
+    // the decoys of the two logins differ, but each stays the same
+    let decoy = RecoveryLogin::decoy("nobody", &[5; 32]);
+    assert_eq!(decoy.0.salt, RecoveryLogin::decoy("nobody", &[5; 32]).0.salt);
+    assert_ne!(decoy.0.salt, UserData::decoy("nobody", &[5; 32]).salt);
+    let typed = key.as_str().to_uppercase();
+    let (key, srv) = login(RecoveryKey::parse(&typed).unwrap().as_str()).unwrap();
+
+    let register = |username| auth::register(&mut rand::rng(), username, "new password", Suite::default()).unwrap();
+    let sealed = auth::Channel::new(&key, auth::Role::Client).seal_to_vec(&register("other")).unwrap();
+    assert_eq!(reset(&srv, &sealed, "user", &user).err(), Some(RecoveryError::WrongUser));
+    assert_eq!(reset(&srv, b"garbage", "user", &user).err(), Some(RecoveryError::Malformed));
+    let sealed = auth::Channel::new(&key, auth::Role::Client).seal_to_vec(&register("user")).unwrap();
+    let new = reset(&srv, &sealed, "user", &user).unwrap();
+    assert_eq!(new.v, Suite::default().compute_verifier(b"user", b"new password", &new.salt));
+    assert!(new.recovery_login().is_some());
+}
@@ -0,0 +1,298 @@
+//! Resumption tickets, so a returning client can skip the SRP exchange. The
+//! exchange is described in `auth_common::resume`.
+//!
+//! A ticket holds the user data, the resumption secret, the time of the
+//! password login and its expiry, sealed with ChaCha20-Poly1305 under a key
+//! only the server knows:
+//!
+//! | nonce (12) | ciphertext + tag |
+//!
+//! Each ticket is accepted once, `Tickets` remembers the used ones until they
+//! expire. A resumption hands out the next ticket, but never one that lasts
+//! past `max_age` after the password login. Times are seconds since the Unix
+//! epoch.
+//!
+//! The key opens every ticket out there, so its holder can resume as anyone
+//! who has one. It does not give the keys of past resumptions, each one mixes
+//! in a fresh Diffie-Hellman exchange. Replace it with `Tickets::rotate`, e.g.
+//! daily. To end the resumptions of one user, e.g. after a password reset,
+//! call `Tickets::revoke_user`. Used tickets and revocations are kept in
+//! memory only, rotate the key after a restart.
+use std::collections::BTreeSet;
+
+pub use auth_common::{ResumeReq, ResumeResp, Ticket};
+use auth_common::{
+    Bytes, ErrorCode,
+    resume::{DhShare, MAX_TICKET_LEN, ResumeSecret},
+};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, aead::Aead};
+use gxhash::HashMap;
+use rand::random;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    Authenticated,
+    channel::{Channel, Role},
+    signing::Verifier,
+};
+
+const NONCE: usize = 12;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TicketError {
+    /// Not a ticket sealed with this key, or modified.
+    Invalid,
+    Expired,
+    /// The ticket was used before.
+    Reused,
+    /// The client does not know the secret of the ticket, or sent a share
+    /// outside the group.
+    BadProof,
+    /// The tickets of the user were revoked after this login.
+    Revoked,
+    /// The data of the login does not fit into `MAX_TICKET_LEN`.
+    TooLong,
+}
+impl TicketError {
+    /// All but `TooLong` mean the client has to log in with the password again.
+    pub fn code(self) -> ErrorCode {
+        match self {
+            TicketError::TooLong => ErrorCode::Internal,
+            _ => ErrorCode::ResumeFailed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Contents<D> {
+    id: [u8; 16],
+    secret: [u8; 32],
+    /// When the password was used.
+    login: u64,
+    expires: u64,
+    data: D,
+}
+
+/// A ticket ready to be sent to the client.
+pub struct SealedTicket {
+    ticket: Vec<u8>,
+    expires: u64,
+}
+impl SealedTicket {
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+    /// The `Ticket` to send after the login.
+    pub fn message(&self) -> Ticket<'_> {
+        Ticket { ticket: Bytes(&self.ticket), expires: self.expires }
+    }
+}
+
+/// A successful resumption. Comes with a new shared key, like `Authenticated`.
+pub struct Resumed<D> {
+    key: [u8; 512],
+    share: DhShare,
+    proof: [u8; 32],
+    ticket: SealedTicket,
+    login: u64,
+    pub data: D,
+}
+impl<D> Resumed<D> {
+    pub fn get_key(&self) -> [u8; 512] {
+        self.key
+    }
+    /// When the password was last used, at the login the ticket goes back to.
+    pub fn login_time(&self) -> u64 {
+        self.login
+    }
+    /// The reply with the proof of the server and the next ticket.
+    pub fn response(&self) -> ResumeResp<'_> {
+        ResumeResp { share: self.share.public(), proof: self.proof, ticket: Bytes(&self.ticket.ticket), expires: self.ticket.expires }
+    }
+    /// Encrypted channel keyed from the new shared key.
+    pub fn channel(&self) -> Channel {
+        Channel::new(&self.key, Role::Server)
+    }
+    /// Verifier for the requests the client signs after the resumption.
+    pub fn request_verifier(&self) -> Verifier {
+        Verifier::new(&self.key)
+    }
+}
+
+/// Issues and checks tickets under one key.
+///
+/// The data of a login is stored in the ticket, it has to stay small enough
+/// for `auth_common::resume::MAX_TICKET_LEN`, a username is fine.
+pub struct Tickets {
+    cipher: ChaCha20Poly1305,
+    /// The key before the last `rotate`.
+    previous: Option<ChaCha20Poly1305>,
+    lifetime: u64,
+    max_age: u64,
+    /// `(expires, id)` of used tickets that have not expired yet.
+    used: BTreeSet<(u64, [u8; 16])>,
+    /// Time of `revoke_user`, by the encoded data of the login.
+    revoked: HashMap<Vec<u8>, u64>,
+}
+impl Tickets {
+    /// Tickets last a day, and are handed out for 30 days after the password
+    /// login. See `with_lifetime` and `with_max_age`.
+    pub fn new(key: &[u8; 32]) -> Tickets {
+        Tickets {
+            cipher: ChaCha20Poly1305::new(key.into()),
+            previous: None,
+            lifetime: 24 * 3600,
+            max_age: 30 * 24 * 3600,
+            used: BTreeSet::new(),
+            revoked: HashMap::default(),
+        }
+    }
+    /// How long a single ticket is valid.
+    pub fn with_lifetime(self, secs: u64) -> Tickets {
+        Tickets { lifetime: secs, ..self }
+    }
+    /// How long after the password login resumption keeps working.
+    pub fn with_max_age(self, secs: u64) -> Tickets {
+        Tickets { max_age: secs, ..self }
+    }
+
+    /// Seal new tickets under `key`. The tickets sealed under the key before
+    /// still work, older ones do not: rotate at most once per `lifetime`.
+    pub fn rotate(&mut self, key: &[u8; 32]) {
+        self.previous = Some(std::mem::replace(&mut self.cipher, ChaCha20Poly1305::new(key.into())));
+    }
+    /// Reject the tickets of the user with `data` that go back to a password
+    /// login up to `now`, in the same second included: a login that raced the
+    /// revocation could be the one it is meant to end. Logins in the seconds
+    /// after `now` get working tickets again.
+    pub fn revoke_user<D: Serialize>(&mut self, data: &D, now: u64) {
+        self.revoked.insert(postcard::to_allocvec(data).expect("data serializes"), now);
+    }
+
+    /// The first ticket, after a login with the password.
+    pub fn issue<D: Serialize>(&self, auth: &Authenticated<D>, now: u64) -> Result<SealedTicket, TicketError> {
+        self.seal(&ResumeSecret::new(&auth.get_key()), now, &auth.data, now)
+    }
+
+    /// Check a `ResumeReq` and use up its ticket.
+    pub fn resume<D: Serialize + DeserializeOwned>(&mut self, req: ResumeReq, now: u64) -> Result<Resumed<D>, TicketError> {
+        let contents: Contents<D> = self.open(&req.ticket)?;
+        if now >= contents.expires {
+            return Err(TicketError::Expired);
+        }
+        let secret = ResumeSecret::from_bytes(contents.secret);
+        // checked first, so a ticket seen on the wire cannot be burned
+        if !secret.verify_client(&req.ticket, req.share, &req.proof) {
+            return Err(TicketError::BadProof);
+        }
+        let share = DhShare::new(random());
+        let dh = share.agree(req.share).map_err(|_| TicketError::BadProof)?;
+        self.clean(now);
+        let user = postcard::to_allocvec(&contents.data).expect("data serializes");
+        if self.revoked.get(&user).is_some_and(|&revoked| contents.login <= revoked) {
+            return Err(TicketError::Revoked);
+        }
+        if !self.used.insert((contents.expires, contents.id)) {
+            return Err(TicketError::Reused);
+        }
+
+        let proof = secret.server_proof(&req.ticket, req.share, share.public());
+        let (key, next) = secret.next(req.share, share.public(), &dh);
+        let ticket = self.seal(&next, contents.login, &contents.data, now)?;
+        Ok(Resumed { key, share, proof, ticket, login: contents.login, data: contents.data })
+    }
+
+    /// Forget used tickets that have expired, and revocations that no
+    /// ticket predates anymore.
+    pub fn clean(&mut self, now: u64) {
+        self.used = self.used.split_off(&(now, [0; 16]));
+        let max_age = self.max_age;
+        self.revoked.retain(|_, revoked| now < revoked.saturating_add(max_age));
+    }
+
+    fn seal<D: Serialize>(&self, secret: &ResumeSecret, login: u64, data: &D, now: u64) -> Result<SealedTicket, TicketError> {
+        let expires = now.saturating_add(self.lifetime).min(login.saturating_add(self.max_age));
+        let contents = Contents { id: random(), secret: *secret.as_bytes(), login, expires, data };
+        let plain = postcard::to_allocvec(&contents).expect("data serializes");
+
+        let nonce: [u8; NONCE] = random();
+        let mut ticket = nonce.to_vec();
+        ticket.extend(self.cipher.encrypt(&nonce.into(), plain.as_slice()).expect("plaintext is short"));
+        if ticket.len() > MAX_TICKET_LEN {
+            return Err(TicketError::TooLong);
+        }
+        Ok(SealedTicket { ticket, expires })
+    }
+    fn open<D: DeserializeOwned>(&self, ticket: &[u8]) -> Result<Contents<D>, TicketError> {
+        let (nonce, sealed) = ticket.split_at_checked(NONCE).ok_or(TicketError::Invalid)?;
+        let plain = self
+            .cipher
+            .decrypt(nonce.into(), sealed)
+            .or_else(|e| self.previous.as_ref().ok_or(e)?.decrypt(nonce.into(), sealed))
+            .map_err(|_| TicketError::Invalid)?;
+        postcard::from_bytes(&plain).map_err(|_| TicketError::Invalid)
+    }
+}
+
+#[test]
+fn test_tickets() {
+    let mut tickets = Tickets::new(&[1; 32]).with_lifetime(100).with_max_age(250);
+    let secret = ResumeSecret::new(&[7; 512]);
+    let first = tickets.seal(&secret, 1000, &"user".to_owned(), 1000).unwrap();
+    assert_eq!(first.expires(), 1100);
+    // never past `max_age` after the password login
+    assert_eq!(tickets.seal(&secret, 1000, &"user".to_owned(), 1200).unwrap().expires(), 1250);
+    assert_eq!(tickets.seal(&secret, 1000, &"x".repeat(MAX_TICKET_LEN), 1000).err(), Some(TicketError::TooLong));
+
+    let client = DhShare::new([3; 32]);
+    fn request<'a>(ticket: &'a SealedTicket, secret: &ResumeSecret, share: &'a DhShare) -> ResumeReq<'a> {
+        let ticket = ticket.message().ticket;
+        ResumeReq { ticket, share: share.public(), proof: secret.client_proof(&ticket, share.public()) }
+    }
+    assert_eq!(tickets.resume::<String>(request(&first, &ResumeSecret::new(&[8; 512]), &client), 1050).err(), Some(TicketError::BadProof));
+    assert_eq!(Tickets::new(&[2; 32]).resume::<String>(request(&first, &secret, &client), 1050).err(), Some(TicketError::Invalid));
+    assert_eq!(tickets.resume::<String>(request(&first, &secret, &client), 1100).err(), Some(TicketError::Expired));
+
+    // a share outside the group fails even with the right proof, and does not use up the ticket
+    let ticket = first.message().ticket;
+    let bad = ResumeReq { ticket, share: &[0; 512], proof: secret.client_proof(&ticket, &[0; 512]) };
+    assert_eq!(tickets.resume::<String>(bad, 1050).err(), Some(TicketError::BadProof));
+
+    let resumed: Resumed<String> = tickets.resume(request(&first, &secret, &client), 1050).unwrap();
+    assert_eq!(resumed.data, "user");
+    assert_eq!(resumed.login_time(), 1000);
+    assert_eq!(tickets.resume::<String>(request(&first, &secret, &client), 1060).err(), Some(TicketError::Reused));
+
+    // the client checks the server and derives the same key and secret
+    let resp = resumed.response();
+    assert!(secret.verify_server(&first.message().ticket, client.public(), resp.share, &resp.proof));
+    let dh = client.agree(resp.share).unwrap();
+    let (key, next) = secret.next(client.public(), resp.share, &dh);
+    assert_eq!(key, resumed.get_key());
+    // the secret and the shares alone do not give the key
+    assert_ne!(secret.next(client.public(), resp.share, &[0; 512]).0, key);
+
+    // the next ticket needs the next secret
+    let second = resumed.ticket;
+    assert_eq!(second.expires(), 1150);
+    assert_eq!(tickets.resume::<String>(request(&second, &secret, &client), 1100).err(), Some(TicketError::BadProof));
+    let resumed: Resumed<String> = tickets.resume(request(&second, &next, &client), 1100).unwrap();
+    assert_eq!(resumed.ticket.expires(), 1200);
+    assert_ne!(resumed.get_key(), key);
+
+    // a revocation covers the password logins up to its time, of that user
+    let secret = ResumeSecret::new(&[9; 512]);
+    let seal = |tickets: &Tickets, login, user: &str| tickets.seal(&secret, login, &user.to_owned(), 1100).unwrap();
+    let (old, new, other) = (seal(&tickets, 1100, "user"), seal(&tickets, 1101, "user"), seal(&tickets, 1100, "other"));
+    tickets.revoke_user(&"user".to_owned(), 1100);
+    assert_eq!(tickets.resume::<String>(request(&old, &secret, &client), 1110).err(), Some(TicketError::Revoked));
+    assert!(tickets.resume::<String>(request(&new, &secret, &client), 1110).is_ok());
+    assert!(tickets.resume::<String>(request(&other, &secret, &client), 1110).is_ok());
+
+    // tickets of the key before the last rotation still work
+    let (before, stale) = (seal(&tickets, 1101, "user"), seal(&tickets, 1101, "user"));
+    tickets.rotate(&[3; 32]);
+    assert!(tickets.resume::<String>(request(&before, &secret, &client), 1120).is_ok());
+    tickets.rotate(&[4; 32]);
+    assert_eq!(tickets.resume::<String>(request(&stale, &secret, &client), 1120).err(), Some(TicketError::Invalid));
+}
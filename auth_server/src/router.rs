@@ -0,0 +1,564 @@
+//! Login routes for axum, enabled by the `axum` feature.
+//!
+//! `routes` handles `POST /register`, `/pre-auth`, `/auth` and `/logout`.
+//! Requests and responses are framed messages, failures are answered with an
+//! `ErrorResp`. A successful login sets the `session` cookie, which the
+//! `AuthUser` extractor checks on the routes of the application. Add those to
+//! the router from `routes`, then pass an `Arc<AuthState>` to `with_state`.
+//!
+//! Routes for sensitive operations take a `RecentUser` instead. It rejects
+//! sessions without a recent password proof with `StepUpRequired`, the client
+//! then runs the login again against `/step-up/pre-auth` and `/step-up/auth`,
+//! with the session cookie.
+//!
+//! Users enrolled in TOTP get a `TotpChallenge` from `/auth` and finish the
+//! login at `/auth/totp`. This needs the key their secrets are sealed with,
+//! see `AuthState::with_totp_key`.
+//!
+//! Logins and step-ups of a username are limited with `limit::Attempts`, see
+//! `AuthState::with_attempt_limit`. Past the limit, `/pre-auth` answers with
+//! `429` and `TooManyAttempts`.
+//!
+//! `/register` answers a name that is taken with `UsernameTaken`, so anyone
+//! can find out whether a user exists, which logins otherwise hide. Where that
+//! matters, turn it off with `AuthState::without_register` and add users
+//! through `CredStore::insert` after checks of your own, e.g. of the email.
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{FromRequestParts, State},
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+
+use crate::{
+    AuthReq, Authenticated, CredHasher, ErrorCode, ErrorResp, Message, PreAuthReq, SrpAuth, Step1,
+    UserData, decode_message, decode_register_req, encode,
+    limit::{Attempts, TooManyAttempts},
+    session::{SessionError, SessionId, SessionStore, Sessions, StepUps},
+    totp::{Totp, TotpError, TotpLogins, TotpReq},
+};
+
+/// Name of the cookie that holds the `SessionId`.
+pub const SESSION_COOKIE: &str = "session";
+
+/// Storage of the user records: a `CredHasher` that can add users, with the
+/// `Send` futures the routes need. Every `CredStore` is a `CredHasher`, so
+/// the same store serves `tcp::Server`.
+pub trait CredStore {
+    fn get(&self, username: &str) -> impl Future<Output = Option<UserData>> + Send;
+    /// Store the record of a new user. Returns `false` if the name is taken.
+    fn insert(&self, username: &str, user: UserData) -> impl Future<Output = bool> + Send;
+}
+impl<C: CredStore> CredHasher for C {
+    fn get_user_data(&self, username: &str) -> impl Future<Output = Option<UserData>> {
+        self.get(username)
+    }
+}
+
+/// Shared state of the routes.
+pub struct AuthState<C, S> {
+    creds: C,
+    sessions: Sessions<S>,
+    logins: Mutex<SrpAuth<(), String>>,
+    step_ups: Mutex<StepUps<String>>,
+    totp_logins: Mutex<TotpLogins<(), String>>,
+    totp_key: Option<[u8; 32]>,
+    decoy_secret: [u8; 32],
+    timeout: Duration,
+    attempts: Mutex<Attempts<String>>,
+    register: bool,
+}
+impl<C, S: SessionStore<User = String>> AuthState<C, S> {
+    /// `decoy_secret` derives the salts of unknown users, see `UserData::decoy`.
+    pub fn new(creds: C, sessions: Sessions<S>, decoy_secret: [u8; 32]) -> Self {
+        AuthState {
+            creds,
+            sessions,
+            logins: Mutex::new(SrpAuth::new()),
+            step_ups: Mutex::new(SrpAuth::new()),
+            totp_logins: Mutex::new(TotpLogins::new()),
+            totp_key: None,
+            decoy_secret,
+            timeout: Duration::from_secs(30),
+            attempts: Mutex::new(Attempts::new(10, 15 * 60)),
+            register: true,
+        }
+    }
+    /// Time between `/pre-auth` and `/auth`, and between `/auth` and
+    /// `/auth/totp`. Defaults to 30 seconds.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        AuthState { timeout, ..self }
+    }
+    /// The key of `UserData::set_totp`. Without it, users enrolled in TOTP
+    /// can not log in.
+    pub fn with_totp_key(self, key: [u8; 32]) -> Self {
+        AuthState { totp_key: Some(key), ..self }
+    }
+    /// Allow `max` logins and step-ups per username within `window` seconds,
+    /// counted from the first. A successful login starts over. Defaults to
+    /// 10 in 15 minutes.
+    pub fn with_attempt_limit(self, max: u32, window: u64) -> Self {
+        AuthState { attempts: Mutex::new(Attempts::new(max, window)), ..self }
+    }
+    /// Answer `/register` with `404`, see the module documentation.
+    pub fn without_register(self) -> Self {
+        AuthState { register: false, ..self }
+    }
+    pub fn creds(&self) -> &C {
+        &self.creds
+    }
+    pub fn sessions(&self) -> &Sessions<S> {
+        &self.sessions
+    }
+    fn totp(&self, user: &UserData) -> Result<Option<Totp>, TotpError> {
+        match self.totp_key {
+            Some(key) => user.totp(&key),
+            None if user.has_totp() => Err(TotpError::Sealed),
+            None => Ok(None),
+        }
+    }
+}
+
+type Shared<C, S> = Arc<AuthState<C, S>>;
+
+/// The login routes. Add the routes of the application, then call `with_state`.
+pub fn routes<C, S>() -> Router<Shared<C, S>>
+where
+    C: CredStore + Send + Sync + 'static,
+    S: SessionStore<User = String> + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/register", post(register::<C, S>))
+        .route("/pre-auth", post(pre_auth::<C, S>))
+        .route("/auth", post(auth::<C, S>))
+        .route("/auth/totp", post(auth_totp::<C, S>))
+        .route("/logout", post(logout::<C, S>))
+        .route("/step-up/pre-auth", post(step_up_pre_auth::<C, S>))
+        .route("/step-up/auth", post(step_up_auth::<C, S>))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn message<'a, M: Message<'a>>(msg: &M) -> Response {
+    match encode(msg) {
+        Some(body) => ([(header::CONTENT_TYPE, "application/octet-stream")], body).into_response(),
+        None => error(ErrorCode::Internal),
+    }
+}
+
+/// Run the 4096-bit arithmetic of a login on the blocking pool, so it holds
+/// up neither the executor nor the other logins.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, ErrorCode> + Send + 'static) -> Result<T, ErrorCode> {
+    tokio::task::spawn_blocking(f).await.unwrap_or(Err(ErrorCode::Internal))
+}
+
+fn error(code: ErrorCode) -> Response {
+    let status = match code {
+        ErrorCode::Malformed | ErrorCode::UnsupportedVersion | ErrorCode::UnsupportedSuite => StatusCode::BAD_REQUEST,
+        ErrorCode::HandshakeExpired | ErrorCode::AuthFailed | ErrorCode::ResumeFailed => StatusCode::UNAUTHORIZED,
+        ErrorCode::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::UsernameTaken => StatusCode::CONFLICT,
+        ErrorCode::StepUpRequired => StatusCode::FORBIDDEN,
+        ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, message(&ErrorResp::new(code))).into_response()
+}
+
+fn too_many(e: TooManyAttempts) -> Response {
+    let headers = [(header::RETRY_AFTER, e.retry_after.to_string())];
+    (StatusCode::TOO_MANY_REQUESTS, headers, message(&e.response())).into_response()
+}
+
+async fn register<C: CredStore, S>(State(state): State<Shared<C, S>>, body: Bytes) -> Response {
+    if !state.register {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Some((username, user)) = decode_register_req(&body) else {
+        return error(ErrorCode::Malformed);
+    };
+    match state.creds.insert(username, user).await {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => error(ErrorCode::UsernameTaken),
+    }
+}
+
+async fn pre_auth<C: CredStore, S>(State(state): State<Shared<C, S>>, body: Bytes) -> Response {
+    let username = match decode_message::<PreAuthReq>(&body) {
+        Ok(req) => req.username.to_owned(),
+        Err(e) => return error(e.code()),
+    };
+    // before the lookup, so unknown users run out the same way
+    if let Err(e) = state.attempts.lock().unwrap().attempt(username.clone(), now()) {
+        return too_many(e);
+    }
+    let user = match state.creds.get(&username).await {
+        Some(user) => user,
+        None => UserData::decoy(&username, &state.decoy_secret),
+    };
+    let expires = Instant::now() + state.timeout;
+    let step1 = blocking(move || {
+        let req: PreAuthReq = decode_message(&body).map_err(|e| e.code())?;
+        Step1::new(&req, &user, username, expires).map_err(|e| e.code())
+    });
+    let step1 = match step1.await {
+        Ok(step1) => step1,
+        Err(code) => return error(code),
+    };
+    let mut logins = state.logins.lock().unwrap();
+    logins.clean(Instant::now());
+    message(&logins.insert((), step1))
+}
+
+async fn auth<C: CredStore, S: SessionStore<User = String>>(State(state): State<Shared<C, S>>, body: Bytes) -> Response {
+    let req: AuthReq = match decode_message(&body) {
+        Ok(req) => req,
+        Err(e) => return error(e.code()),
+    };
+    let result = state.logins.lock().unwrap().take(&req, (), Instant::now());
+    let step1 = match result {
+        Ok(step1) => step1,
+        Err(e) => return error(e.code()),
+    };
+    let authenticated = match blocking(move || step1.step2(req).map_err(|e| e.code())).await {
+        Ok(authenticated) => authenticated,
+        Err(code) => return error(code),
+    };
+    // a record that went away since `/pre-auth` fails the login, it might
+    // have had a second factor
+    let totp = match state.creds.get(&authenticated.data).await {
+        Some(user) => state.totp(&user),
+        None => return error(ErrorCode::AuthFailed),
+    };
+    match totp {
+        Ok(None) => logged_in(&state, authenticated).await,
+        Ok(Some(totp)) => {
+            let mut totp_logins = state.totp_logins.lock().unwrap();
+            let now = now();
+            totp_logins.clean(now);
+            message(&totp_logins.challenge(authenticated, totp, (), now + state.timeout.as_secs()))
+        }
+        Err(e) => error(e.code()),
+    }
+}
+
+async fn auth_totp<C, S: SessionStore<User = String>>(State(state): State<Shared<C, S>>, body: Bytes) -> Response {
+    let req: TotpReq = match decode_message(&body) {
+        Ok(req) => req,
+        Err(e) => return error(e.code()),
+    };
+    let result = state.totp_logins.lock().unwrap().verify(req, (), now());
+    match result {
+        Ok(authenticated) => logged_in(&state, authenticated).await,
+        Err(e) => error(e.code()),
+    }
+}
+
+/// Start the session and send the `AuthResponse`.
+async fn logged_in<C, S: SessionStore<User = String>>(state: &AuthState<C, S>, authenticated: Authenticated<String>) -> Response {
+    state.attempts.lock().unwrap().reset(&authenticated.data);
+    let id = state.sessions.create(authenticated.data.clone(), now()).await;
+    let cookie = format!("{SESSION_COOKIE}={}; Path=/; HttpOnly; Secure; SameSite=Strict", id.to_hex());
+    ([(header::SET_COOKIE, cookie)], message(&authenticated.response())).into_response()
+}
+
+async fn logout<C, S: SessionStore<User = String>>(State(state): State<Shared<C, S>>, user: AuthUser) -> Response {
+    state.sessions.logout(&user.session).await;
+    let cookie = format!("{SESSION_COOKIE}=; Path=/; HttpOnly; Secure; SameSite=Strict; Max-Age=0");
+    ([(header::SET_COOKIE, cookie)], StatusCode::NO_CONTENT).into_response()
+}
+
+async fn step_up_pre_auth<C: CredStore, S>(State(state): State<Shared<C, S>>, user: AuthUser, body: Bytes) -> Response {
+    let username = match decode_message::<PreAuthReq>(&body) {
+        Ok(req) => req.username.to_owned(),
+        Err(e) => return error(e.code()),
+    };
+    if let Err(e) = state.attempts.lock().unwrap().attempt(user.username.clone(), now()) {
+        return too_many(e);
+    }
+    // a different name fails like a wrong password, at `/step-up/auth`
+    let record = match username == user.username {
+        true => state.creds.get(&username).await,
+        false => None,
+    };
+    let record = record.unwrap_or_else(|| UserData::decoy(&username, &state.decoy_secret));
+    let expires = Instant::now() + state.timeout;
+    let step1 = blocking(move || {
+        let req: PreAuthReq = decode_message(&body).map_err(|e| e.code())?;
+        Step1::new(&req, &record, user.username, expires).map_err(|e| e.code())
+    });
+    let step1 = match step1.await {
+        Ok(step1) => step1,
+        Err(code) => return error(code),
+    };
+    let mut step_ups = state.step_ups.lock().unwrap();
+    step_ups.clean(Instant::now());
+    message(&step_ups.insert(user.session, step1))
+}
+
+async fn step_up_auth<C, S: SessionStore<User = String>>(State(state): State<Shared<C, S>>, user: AuthUser, body: Bytes) -> Response {
+    let req: AuthReq = match decode_message(&body) {
+        Ok(req) => req,
+        Err(e) => return error(e.code()),
+    };
+    let result = state.step_ups.lock().unwrap().take(&req, user.session, Instant::now());
+    let step1 = match result {
+        Ok(step1) => step1,
+        Err(e) => return error(e.code()),
+    };
+    let authenticated = match blocking(move || step1.step2(req).map_err(|e| e.code())).await {
+        Ok(authenticated) => authenticated,
+        Err(code) => return error(code),
+    };
+    match state.sessions.step_up(&user.session, &authenticated, now()).await {
+        Ok(()) => {
+            state.attempts.lock().unwrap().reset(&user.username);
+            message(&authenticated.response())
+        }
+        Err(_) => error(ErrorCode::AuthFailed),
+    }
+}
+
+/// The user of a request with a valid session cookie. Requests without one are
+/// answered with `401` and an `ErrorResp`.
+pub struct AuthUser {
+    pub username: String,
+    pub session: SessionId,
+}
+
+impl<C, S> FromRequestParts<Shared<C, S>> for AuthUser
+where
+    C: Send + Sync,
+    S: SessionStore<User = String> + Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Shared<C, S>) -> Result<Self, Response> {
+        let session = session_cookie(parts).ok_or_else(|| error(ErrorCode::AuthFailed))?;
+        match state.sessions.check(&session, now()).await {
+            Ok(s) => Ok(AuthUser { username: s.user, session }),
+            Err(_) => Err(error(ErrorCode::AuthFailed)),
+        }
+    }
+}
+
+/// `AuthUser` whose password was proven within the last `MAX_AGE` seconds,
+/// for sensitive operations. Stale sessions are answered with `403` and
+/// `StepUpRequired`.
+pub struct RecentUser<const MAX_AGE: u64>(pub AuthUser);
+
+impl<C, S, const MAX_AGE: u64> FromRequestParts<Shared<C, S>> for RecentUser<MAX_AGE>
+where
+    C: Send + Sync,
+    S: SessionStore<User = String> + Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Shared<C, S>) -> Result<Self, Response> {
+        let session = session_cookie(parts).ok_or_else(|| error(ErrorCode::AuthFailed))?;
+        match state.sessions.check_recent(&session, MAX_AGE, now()).await {
+            Ok(s) => Ok(RecentUser(AuthUser { username: s.user, session })),
+            Err(SessionError::StepUpRequired) => Err(error(ErrorCode::StepUpRequired)),
+            Err(_) => Err(error(ErrorCode::AuthFailed)),
+        }
+    }
+}
+
+fn session_cookie(parts: &Parts) -> Option<SessionId> {
+    parts
+        .headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+        .and_then(SessionId::from_hex)
+}
+
+#[cfg(test)]
+struct Creds(Mutex<std::collections::HashMap<String, UserData>>);
+#[cfg(test)]
+impl CredStore for Creds {
+    async fn get(&self, username: &str) -> Option<UserData> {
+        self.0.lock().unwrap().get(username).cloned()
+    }
+    async fn insert(&self, username: &str, user: UserData) -> bool {
+        let mut users = self.0.lock().unwrap();
+        if users.contains_key(username) {
+            return false;
+        }
+        users.insert(username.into(), user);
+        true
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_routes() {
+    use crate::session::MemoryStore;
+    use axum::{body::Body, http::Request, routing::get};
+    use tower::ServiceExt;
+
+    let state = AuthState::new(Creds(Mutex::default()), Sessions::new(MemoryStore::default()), [1; 32]);
+    let state = Arc::new(state.with_totp_key([2; 32]));
+    let app = routes()
+        .route("/me", get(|user: AuthUser| async move { user.username }))
+        .route("/recent", get(|RecentUser(user): RecentUser<60>| async move { user.username }))
+        .route("/stale", get(|RecentUser(user): RecentUser<0>| async move { user.username }))
+        .with_state(state.clone());
+
+    let post = |uri: &str, body: Vec<u8>, cookie: Option<&str>| {
+        let req = Request::post(uri);
+        let req = match cookie {
+            Some(cookie) => req.header(header::COOKIE, cookie),
+            None => req,
+        };
+        app.clone().oneshot(req.body(Body::from(body)).unwrap())
+    };
+    let body = |resp: Response| async { axum::body::to_bytes(resp.into_body(), 4096).await.unwrap() };
+
+    let req = auth::register(&mut rand::rng(), "user", "password", crate::Suite::default()).unwrap();
+    assert_eq!(post("/register", req.clone(), None).await.unwrap().status(), StatusCode::NO_CONTENT);
+    let resp = post("/register", req, None).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    assert_eq!(auth::decode_error(&body(resp).await).unwrap().code, ErrorCode::UsernameTaken);
+
+    let login = auth::Login::new(&mut rand::rng());
+    let resp = post("/pre-auth", login.request("user").unwrap(), None).await.unwrap();
+    let proof = login.process(&body(resp).await, "user", "password").unwrap();
+    let resp = post("/auth", proof.request().unwrap(), None).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap().split(';').next().unwrap().to_owned();
+    proof.finish(&body(resp).await).unwrap();
+
+    let me = |cookie: Option<&str>| {
+        let req = Request::get("/me");
+        let req = match cookie {
+            Some(cookie) => req.header(header::COOKIE, cookie),
+            None => req,
+        };
+        app.clone().oneshot(req.body(Body::empty()).unwrap())
+    };
+    assert_eq!(body(me(Some(&cookie)).await.unwrap()).await, "user");
+    assert_eq!(me(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    let get = |uri: &str| app.clone().oneshot(Request::get(uri).header(header::COOKIE, &cookie).body(Body::empty()).unwrap());
+    assert_eq!(body(get("/recent").await.unwrap()).await, "user");
+    let resp = get("/stale").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(auth::decode_error(&body(resp).await).unwrap().code, ErrorCode::StepUpRequired);
+
+    // step-up within the session, only for its own user
+    let login = auth::Login::new(&mut rand::rng());
+    let resp = post("/step-up/pre-auth", login.request("user").unwrap(), Some(&cookie)).await.unwrap();
+    let proof = login.process(&body(resp).await, "user", "password").unwrap();
+    let resp = post("/step-up/auth", proof.request().unwrap(), Some(&cookie)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    proof.finish(&body(resp).await).unwrap();
+    let login = auth::Login::new(&mut rand::rng());
+    assert_eq!(post("/step-up/pre-auth", login.request("user").unwrap(), None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    let resp = post("/step-up/pre-auth", login.request("other").unwrap(), Some(&cookie)).await.unwrap();
+    let proof = login.process(&body(resp).await, "other", "password").unwrap();
+    let resp = post("/step-up/auth", proof.request().unwrap(), Some(&cookie)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(post("/logout", vec![], Some(&cookie)).await.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(me(Some(&cookie)).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    // a wrong password fails like an unknown user
+    for username in ["user", "nobody"] {
+        let login = auth::Login::new(&mut rand::rng());
+        let resp = post("/pre-auth", login.request(username).unwrap(), None).await.unwrap();
+        let proof = login.process(&body(resp).await, username, "wrong").unwrap();
+        let resp = post("/auth", proof.request().unwrap(), None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(auth::decode_error(&body(resp).await).unwrap().code, ErrorCode::AuthFailed);
+    }
+
+    // a record removed between `/pre-auth` and `/auth` fails the login
+    let req = auth::register(&mut rand::rng(), "gone", "password", crate::Suite::default()).unwrap();
+    post("/register", req, None).await.unwrap();
+    let login = auth::Login::new(&mut rand::rng());
+    let resp = post("/pre-auth", login.request("gone").unwrap(), None).await.unwrap();
+    let proof = login.process(&body(resp).await, "gone", "password").unwrap();
+    state.creds().0.lock().unwrap().remove("gone");
+    let resp = post("/auth", proof.request().unwrap(), None).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(!resp.headers().contains_key(header::SET_COOKIE));
+
+    // the second factor, each code works once
+    let req = auth::register(&mut rand::rng(), "totp", "password", crate::Suite::default()).unwrap();
+    post("/register", req, None).await.unwrap();
+    let totp = Totp::generate();
+    state.creds().0.lock().unwrap().get_mut("totp").unwrap().set_totp(Some(&totp), &[2; 32]);
+    let code = totp.code(now());
+    let mut statuses = vec![];
+    for code in [(code + 1) % 1_000_000, code, code] {
+        let login = auth::Login::new(&mut rand::rng());
+        let resp = post("/pre-auth", login.request("totp").unwrap(), None).await.unwrap();
+        let proof = login.process(&body(resp).await, "totp", "password").unwrap();
+        let resp = body(post("/auth", proof.request().unwrap(), None).await.unwrap()).await;
+        assert_eq!(proof.totp_challenge(&resp).unwrap(), Some(6));
+        let resp = post("/auth/totp", proof.totp_request(&resp, code).unwrap(), None).await.unwrap();
+        statuses.push(resp.status());
+        if resp.status() == StatusCode::OK {
+            assert!(resp.headers().contains_key(header::SET_COOKIE));
+            proof.finish(&body(resp).await).unwrap();
+        }
+    }
+    assert_eq!(statuses, [StatusCode::UNAUTHORIZED, StatusCode::OK, StatusCode::UNAUTHORIZED]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_limits() {
+    use crate::session::MemoryStore;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    let creds = Creds(Mutex::default());
+    creds.insert("user", UserData::test_user("password")).await;
+    // the routes and `tcp::Server` share the store
+    assert!(creds.get_user_data("user").await.is_some());
+    let state = AuthState::new(creds, Sessions::new(MemoryStore::default()), [1; 32]);
+    let app = routes().with_state(Arc::new(state.with_attempt_limit(2, 60).without_register()));
+    let post = |uri: &str, body: Vec<u8>| app.clone().oneshot(Request::post(uri).body(Body::from(body)).unwrap());
+    let body = |resp: Response| async { axum::body::to_bytes(resp.into_body(), 4096).await.unwrap() };
+
+    let req = auth::register(&mut rand::rng(), "new", "password", crate::Suite::default()).unwrap();
+    assert_eq!(post("/register", req).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+    let pre_auth = |username: &'static str| async move {
+        let login = auth::Login::new(&mut rand::rng());
+        let resp = post("/pre-auth", login.request(username).unwrap()).await.unwrap();
+        (login, resp)
+    };
+
+    // a login starts over
+    pre_auth("user").await;
+    let (login, resp) = pre_auth("user").await;
+    let proof = login.process(&body(resp).await, "user", "password").unwrap();
+    assert_eq!(post("/auth", proof.request().unwrap()).await.unwrap().status(), StatusCode::OK);
+
+    // unknown users run out like real ones
+    for username in ["user", "nobody"] {
+        for _ in 0..2 {
+            assert_eq!(pre_auth(username).await.1.status(), StatusCode::OK);
+        }
+        let resp = pre_auth(username).await.1;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = resp.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&retry_after));
+        let resp = auth::decode_error(&body(resp).await).unwrap();
+        assert_eq!((resp.code, resp.retry_after), (ErrorCode::TooManyAttempts, Some(retry_after as u32)));
+    }
+}
@@ -0,0 +1,293 @@
+//! Sessions for logged in users.
+//!
+//! `Sessions` issues a `SessionId` after each successful login and checks
+//! it on later requests. Where the sessions live is up to the `SessionStore`,
+//! `MemoryStore` keeps them in a `HashMap`. Times are seconds since the Unix epoch.
+//!
+//! Sensitive operations can ask for a recent password proof with
+//! `Sessions::check_recent`. The user gives one with a step-up login inside
+//! the session: run it with `StepUps`, keyed by the `SessionId`, so it can not
+//! be finished from another session, then pass the result to `Sessions::step_up`.
+use std::{collections::HashMap, future::Future, sync::Mutex};
+
+use rand::random;
+use serde::{Deserialize, Serialize};
+
+use crate::{Authenticated, SrpAuth, resume::Resumed};
+
+/// Opaque random session identifier.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SessionId(#[serde(with = "serdapt_base64::StdBase64Array")] [u8; 32]);
+impl SessionId {
+    pub fn from_bytes(bytes: [u8; 32]) -> SessionId {
+        SessionId(bytes)
+    }
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+    /// The id as 64 hex digits, e.g. for a cookie.
+    pub fn to_hex(&self) -> String {
+        crate::to_hex(&self.0)
+    }
+    pub fn from_hex(hex: &str) -> Option<SessionId> {
+        crate::from_hex(hex).map(SessionId)
+    }
+}
+/// Does not print the id, it is as good as a password.
+impl std::fmt::Debug for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SessionId(..)")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session<U> {
+    pub user: U,
+    pub created: u64,
+    pub last_seen: u64,
+    /// When the user last proved the password, at login or in a step-up.
+    #[serde(default)]
+    pub authenticated: u64,
+}
+
+/// Pending step-up logins, keyed by the session they were started in.
+pub type StepUps<U> = SrpAuth<SessionId, U>;
+
+/// Storage of the sessions, shared by all requests.
+pub trait SessionStore {
+    type User: Clone + Eq + Send + Sync;
+    fn insert(&self, id: SessionId, session: Session<Self::User>) -> impl Future<Output = ()> + Send;
+    fn get(&self, id: &SessionId) -> impl Future<Output = Option<Session<Self::User>>> + Send;
+    /// Record activity on the session and return it, in one step. If
+    /// `expired` returns an error for the stored session, remove it instead
+    /// and return the error. A missing session is `SessionError::Unknown`.
+    fn check_and_touch(
+        &self,
+        id: &SessionId,
+        now: u64,
+        expired: impl FnOnce(&Session<Self::User>) -> Option<SessionError> + Send,
+    ) -> impl Future<Output = Result<Session<Self::User>, SessionError>> + Send;
+    /// Record a step-up login on the session.
+    fn mark_authenticated(&self, id: &SessionId, now: u64) -> impl Future<Output = ()> + Send;
+    fn remove(&self, id: &SessionId) -> impl Future<Output = ()> + Send;
+    /// Remove all sessions of `user` and return how many there were.
+    fn remove_user(&self, user: &Self::User) -> impl Future<Output = usize> + Send;
+}
+
+/// Sessions in memory. They are lost on restart.
+pub struct MemoryStore<U> {
+    sessions: Mutex<HashMap<SessionId, Session<U>>>,
+}
+impl<U> Default for MemoryStore<U> {
+    fn default() -> Self {
+        MemoryStore { sessions: Mutex::new(HashMap::new()) }
+    }
+}
+impl<U> MemoryStore<U> {
+    /// Drop the sessions that `Sessions::check` would reject anyway.
+    pub fn clean(&self, sessions: &Sessions<Self>, now: u64)
+    where
+        U: Clone + Eq + Send + Sync,
+    {
+        self.sessions.lock().unwrap().retain(|_, s| sessions.expired(s, now).is_none());
+    }
+}
+impl<U: Clone + Eq + Send + Sync> SessionStore for MemoryStore<U> {
+    type User = U;
+    async fn insert(&self, id: SessionId, session: Session<U>) {
+        self.sessions.lock().unwrap().insert(id, session);
+    }
+    async fn get(&self, id: &SessionId) -> Option<Session<U>> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+    async fn check_and_touch(
+        &self,
+        id: &SessionId,
+        now: u64,
+        expired: impl FnOnce(&Session<U>) -> Option<SessionError> + Send,
+    ) -> Result<Session<U>, SessionError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(id).ok_or(SessionError::Unknown)?;
+        if let Some(e) = expired(session) {
+            sessions.remove(id);
+            return Err(e);
+        }
+        session.last_seen = now;
+        Ok(session.clone())
+    }
+    async fn mark_authenticated(&self, id: &SessionId, now: u64) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+            session.authenticated = now;
+        }
+    }
+    async fn remove(&self, id: &SessionId) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+    async fn remove_user(&self, user: &U) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, s| s.user != *user);
+        before - sessions.len()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SessionError {
+    /// No such session, or it was logged out or revoked.
+    Unknown,
+    /// No activity for longer than the idle timeout.
+    Idle,
+    /// Older than the absolute lifetime.
+    Expired,
+    /// The last password proof is too old for this operation. Do a step-up login.
+    StepUpRequired,
+}
+
+pub struct Sessions<S> {
+    store: S,
+    idle_timeout: u64,
+    lifetime: u64,
+}
+impl<S: SessionStore> Sessions<S> {
+    /// Sessions end after 30 minutes without activity or after 12 hours,
+    /// see `with_idle_timeout` and `with_lifetime`.
+    pub fn new(store: S) -> Self {
+        Sessions { store, idle_timeout: 30 * 60, lifetime: 12 * 60 * 60 }
+    }
+    pub fn with_idle_timeout(self, secs: u64) -> Self {
+        Sessions { idle_timeout: secs, ..self }
+    }
+    pub fn with_lifetime(self, secs: u64) -> Self {
+        Sessions { lifetime: secs, ..self }
+    }
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Start a session for `user` after a successful login. Each call
+    /// starts a new session with a new id.
+    ///
+    /// The id is random rather than derived from the key of the login: a
+    /// derived id would be the same for every session of one login, and
+    /// anyone who learns the key could compute it.
+    pub async fn create(&self, user: S::User, now: u64) -> SessionId {
+        self.insert(Session { user, created: now, last_seen: now, authenticated: now }).await
+    }
+    /// Start a session after a resumption. For `check_recent` it counts as
+    /// authenticated when the password was used, not now.
+    pub async fn resume<D>(&self, resumed: &Resumed<D>, user: S::User, now: u64) -> SessionId {
+        let authenticated = resumed.login_time();
+        self.insert(Session { user, created: now, last_seen: now, authenticated }).await
+    }
+    async fn insert(&self, session: Session<S::User>) -> SessionId {
+        let id = SessionId(random());
+        self.store.insert(id, session).await;
+        id
+    }
+    /// Look up the session of a request and count it as activity.
+    /// Expired sessions are removed.
+    pub async fn check(&self, id: &SessionId, now: u64) -> Result<Session<S::User>, SessionError> {
+        let (idle_timeout, lifetime) = (self.idle_timeout, self.lifetime);
+        self.store.check_and_touch(id, now, move |session| expired(session, idle_timeout, lifetime, now)).await
+    }
+    /// `check`, and require the password to be proven within `max_age` seconds.
+    pub async fn check_recent(&self, id: &SessionId, max_age: u64, now: u64) -> Result<Session<S::User>, SessionError> {
+        let session = self.check(id, now).await?;
+        if now >= session.authenticated.saturating_add(max_age) {
+            return Err(SessionError::StepUpRequired);
+        }
+        Ok(session)
+    }
+    /// Mark the session as recently authenticated after a step-up login.
+    ///
+    /// `auth` comes from the `StepUps` entry keyed by `id`, its data is the
+    /// user the login was for. That has to be the user of the session.
+    pub async fn step_up(&self, id: &SessionId, auth: &Authenticated<S::User>, now: u64) -> Result<(), SessionError> {
+        let session = self.check(id, now).await?;
+        if session.user != auth.data {
+            return Err(SessionError::Unknown);
+        }
+        self.store.mark_authenticated(id, now).await;
+        Ok(())
+    }
+    pub async fn logout(&self, id: &SessionId) {
+        self.store.remove(id).await;
+    }
+    /// End all sessions of `user`, e.g. after a password change.
+    pub async fn revoke_user(&self, user: &S::User) -> usize {
+        self.store.remove_user(user).await
+    }
+
+    fn expired(&self, session: &Session<S::User>, now: u64) -> Option<SessionError> {
+        expired(session, self.idle_timeout, self.lifetime, now)
+    }
+}
+
+fn expired<U>(session: &Session<U>, idle_timeout: u64, lifetime: u64, now: u64) -> Option<SessionError> {
+    if now >= session.created.saturating_add(lifetime) {
+        Some(SessionError::Expired)
+    } else if now >= session.last_seen.saturating_add(idle_timeout) {
+        Some(SessionError::Idle)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_sessions() {
+    use crate::UserData;
+    use auth_common::ClientStart;
+    use std::time::{Duration, Instant};
+
+    let user = UserData::test_user("password");
+
+    let sessions = Sessions::new(MemoryStore::default()).with_idle_timeout(10).with_lifetime(100);
+    let id = sessions.create("user", 1000).await;
+    assert_eq!(sessions.check(&id, 1009).await.unwrap().user, "user");
+    assert_eq!(sessions.check(&id, 1018).await.unwrap().last_seen, 1018);
+    assert_eq!(sessions.check(&id, 1028).await, Err(SessionError::Idle));
+    assert_eq!(sessions.check(&id, 1028).await, Err(SessionError::Unknown));
+
+    let id = sessions.create("user", 1000).await;
+    for now in (1005..1100).step_by(5) {
+        assert!(sessions.check(&id, now).await.is_ok());
+    }
+    assert_eq!(sessions.check(&id, 1100).await, Err(SessionError::Expired));
+
+    // a step-up login bound to the session
+    let id = sessions.create("user", 1000).await;
+    assert!(sessions.check_recent(&id, 5, 1004).await.is_ok());
+    assert_eq!(sessions.check_recent(&id, 5, 1005).await, Err(SessionError::StepUpRequired));
+    let mut step_ups = StepUps::new();
+    let client = ClientStart::new([5; 512]);
+    let expires = Instant::now() + Duration::from_secs(10);
+    let resp = step_ups.pre_auth(client.message("user"), &user, id, "user", expires).unwrap();
+    let proof = client.process(&resp, "user", "password").unwrap();
+    let elsewhere = SessionId::from_bytes([0; 32]);
+    assert!(step_ups.auth(proof.message(), elsewhere, Instant::now()).is_err());
+    let auth = step_ups.auth(proof.message(), id, Instant::now()).unwrap();
+    sessions.step_up(&id, &auth, 1006).await.unwrap();
+    assert!(sessions.check_recent(&id, 5, 1010).await.is_ok());
+    sessions.logout(&id).await;
+    assert_eq!(sessions.step_up(&id, &auth, 1007).await, Err(SessionError::Unknown));
+
+    let id = sessions.create("user", 1000).await;
+    sessions.logout(&id).await;
+    assert_eq!(sessions.check(&id, 1001).await, Err(SessionError::Unknown));
+    // a new session for the same user, the logged out one stays out
+    let again = sessions.create("user", 1000).await;
+    assert_ne!(again, id);
+    assert_eq!(sessions.check(&id, 1001).await, Err(SessionError::Unknown));
+    assert!(sessions.check(&again, 1001).await.is_ok());
+
+    let ids = [sessions.create("user", 1000).await, sessions.create("user", 1000).await];
+    assert_ne!(ids[0], ids[1]);
+    let other = sessions.create("other", 1000).await;
+    assert_eq!(sessions.revoke_user(&"user").await, 3);
+    assert_eq!(sessions.check(&ids[1], 1001).await, Err(SessionError::Unknown));
+    assert!(sessions.check(&other, 1001).await.is_ok());
+    assert_eq!(SessionId::from_hex(&other.to_hex()), Some(other));
+    assert_eq!(SessionId::from_hex("+0"), None);
+}
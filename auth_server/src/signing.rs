@@ -0,0 +1,87 @@
+//! Checking request signatures made with `auth::Signer`. The scheme is
+//! described in `auth_common::signing`.
+use std::collections::BTreeSet;
+
+pub use auth_common::signing::Signature;
+use auth_common::signing::SigningKey;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The timestamp is outside of the window.
+    Stale,
+    /// The MAC does not match the request.
+    Invalid,
+    /// The signature was accepted before.
+    Replayed,
+}
+
+/// Verifies the requests of one login. Each signature is accepted once.
+pub struct Verifier {
+    key: SigningKey,
+    window: u64,
+    /// Accepted `(timestamp, nonce)` pairs that are still within the window.
+    seen: BTreeSet<(u64, [u8; 16])>,
+    /// The latest `now` a signature was accepted at. Pruning goes by it, so a
+    /// clock that steps back does not bring pruned signatures back.
+    latest: u64,
+}
+impl Verifier {
+    /// Key the verifier with `Authenticated::get_key`. Requests may be five
+    /// minutes off, see `with_window`.
+    pub fn new(key: &[u8; 512]) -> Verifier {
+        Verifier { key: SigningKey::new(key), window: 300, seen: BTreeSet::new(), latest: 0 }
+    }
+    /// Accept timestamps up to `secs` seconds away from the current time.
+    pub fn with_window(self, secs: u64) -> Verifier {
+        Verifier { window: secs, ..self }
+    }
+    /// Check `sig` for the request. `now` is the current time in seconds since
+    /// the Unix epoch.
+    ///
+    /// Timestamps before the window of the latest accepted `now` are stale as
+    /// well, even if `now` went back since.
+    pub fn verify(&mut self, method: &str, path: &str, body: &[u8], sig: &Signature, now: u64) -> Result<(), SignatureError> {
+        let latest = self.latest.max(now);
+        let oldest = latest.saturating_sub(self.window);
+        if sig.timestamp.abs_diff(now) > self.window || sig.timestamp < oldest {
+            return Err(SignatureError::Stale);
+        }
+        if !self.key.verify(method, path, body, sig) {
+            return Err(SignatureError::Invalid);
+        }
+        // older entries are rejected as stale already
+        self.latest = latest;
+        self.seen = self.seen.split_off(&(oldest, [0; 16]));
+        if !self.seen.insert((sig.timestamp, sig.nonce)) {
+            return Err(SignatureError::Replayed);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_verifier() {
+    let key = [7; 512];
+    let signer = SigningKey::new(&key);
+    let mut verifier = Verifier::new(&key).with_window(60);
+
+    let sig = signer.sign("GET", "/me", b"", 1000, [1; 16]);
+    assert_eq!(verifier.verify("GET", "/me", b"", &sig, 1010), Ok(()));
+    assert_eq!(verifier.verify("GET", "/me", b"", &sig, 1010), Err(SignatureError::Replayed));
+    assert_eq!(verifier.verify("GET", "/you", b"", &sig, 1010), Err(SignatureError::Invalid));
+    assert_eq!(verifier.verify("GET", "/me", b"", &sig, 1061), Err(SignatureError::Stale));
+
+    let early = signer.sign("GET", "/me", b"", 955, [2; 16]);
+    assert_eq!(verifier.verify("GET", "/me", b"", &early, 1000), Ok(()));
+    // still remembered while it is within the window
+    assert_eq!(verifier.verify("GET", "/me", b"", &early, 999), Err(SignatureError::Replayed));
+    let late = signer.sign("GET", "/me", b"", 1060, [3; 16]);
+    assert_eq!(verifier.verify("GET", "/me", b"", &late, 1060), Ok(()));
+    assert_eq!(verifier.seen.len(), 2);
+
+    // a clock that steps back does not bring back pruned signatures
+    let later = signer.sign("GET", "/me", b"", 1200, [4; 16]);
+    assert_eq!(verifier.verify("GET", "/me", b"", &later, 1200), Ok(()));
+    assert_eq!(verifier.verify("GET", "/me", b"", &early, 980), Err(SignatureError::Stale));
+    assert_eq!(verifier.verify("GET", "/me", b"", &later, 1150), Err(SignatureError::Replayed));
+}
@@ -0,0 +1,248 @@
+//! Reference login over TCP, meant for loopback testing.
+//!
+//! One login per connection: the client sends `PreAuthReq` and `AuthReq`, the
+//! server answers with `PreAuthResp` and `AuthResponse`. Failures are answered
+//! with an `ErrorResp` and end the connection.
+use auth_common::ClientStart;
+use futures_util::{SinkExt, StreamExt};
+use srp::{Encoding, U4096};
+use std::{
+    io,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::LocalSet,
+};
+use tokio_util::codec::Framed;
+
+use crate::codec::{CodecError, MessageCodec, RawFrame};
+use crate::{
+    AuthError, Authenticated, CredHasher, ErrorResp, FrameError, HandshakeError, MessageType,
+    PreAuthReq, SrpAuth, Step1, UserData, rand_num,
+};
+
+#[derive(Debug)]
+pub enum LoginError {
+    Codec(CodecError),
+    /// The peer closed the connection in the middle of the login.
+    Closed,
+    /// The peer did not finish the login in time.
+    Timeout,
+    /// The login failed on the server.
+    Auth(AuthError),
+    /// The server answered with an `ErrorResp`.
+    Rejected(ErrorResp),
+}
+impl LoginError {
+    /// The response the server sends for this error, if any.
+    fn response(&self) -> Option<ErrorResp> {
+        match self {
+            LoginError::Codec(e) => e.response(),
+            LoginError::Auth(e) => Some(e.response()),
+            LoginError::Closed | LoginError::Timeout | LoginError::Rejected(_) => None,
+        }
+    }
+}
+impl From<CodecError> for LoginError {
+    fn from(e: CodecError) -> Self {
+        LoginError::Codec(e)
+    }
+}
+impl From<FrameError> for LoginError {
+    fn from(e: FrameError) -> Self {
+        LoginError::Codec(CodecError::Frame(e))
+    }
+}
+impl From<HandshakeError> for LoginError {
+    fn from(e: HandshakeError) -> Self {
+        LoginError::Auth(e.into())
+    }
+}
+impl From<AuthError> for LoginError {
+    fn from(e: AuthError) -> Self {
+        LoginError::Auth(e)
+    }
+}
+
+type Conn = Framed<TcpStream, MessageCodec>;
+
+/// Read the next frame. An `ErrorResp` from the peer becomes `LoginError::Rejected`.
+async fn next_frame(conn: &mut Conn) -> Result<RawFrame, LoginError> {
+    let frame = conn.next().await.ok_or(LoginError::Closed)??;
+    if frame.kind == MessageType::ErrorResp {
+        return Err(LoginError::Rejected(frame.parse()?));
+    }
+    Ok(frame)
+}
+
+/// Run the 4096-bit arithmetic of a login on the blocking pool, so it holds
+/// up neither the executor nor the other logins.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, LoginError> + Send + 'static) -> Result<T, LoginError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+pub struct Server<C> {
+    creds: C,
+    decoy_secret: [u8; 32],
+    timeout: Duration,
+}
+impl<C: CredHasher> Server<C> {
+    /// `decoy_secret` derives the salts of unknown users, see `UserData::decoy`.
+    pub fn new(creds: C, decoy_secret: [u8; 32]) -> Self {
+        Server { creds, decoy_secret, timeout: Duration::from_secs(30) }
+    }
+    /// Time a client has to finish the login. Defaults to 30 seconds.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Server { timeout, ..self }
+    }
+
+    /// Accept connections and run one login on each, every connection in its
+    /// own task. `on_login` is called with the result of every login.
+    ///
+    /// The tasks stay on the thread of `run`, so `C` and its lookups need not
+    /// be `Send`. The 4096-bit arithmetic runs on the blocking pool.
+    pub async fn run(
+        self: Arc<Self>,
+        listener: TcpListener,
+        on_login: impl Fn(Result<Authenticated<String>, LoginError>) + 'static,
+    ) -> io::Result<()>
+    where
+        C: 'static,
+    {
+        let on_login = Rc::new(on_login);
+        let tasks = LocalSet::new();
+        tasks.run_until(async {
+            loop {
+                let (stream, _) = listener.accept().await?;
+                let (server, on_login) = (self.clone(), on_login.clone());
+                tokio::task::spawn_local(async move { on_login(server.handle(stream).await) });
+            }
+        }).await
+    }
+
+    /// Run the login on a single connection.
+    pub async fn handle(&self, stream: TcpStream) -> Result<Authenticated<String>, LoginError> {
+        let mut conn = Framed::new(stream, MessageCodec);
+        let result = match tokio::time::timeout(self.timeout, self.login(&mut conn)).await {
+            Ok(result) => result,
+            Err(_) => Err(LoginError::Timeout),
+        };
+        if let Err(e) = &result
+            && let Some(resp) = e.response()
+        {
+            let _ = conn.send(&resp).await;
+        }
+        result
+    }
+
+    async fn login(&self, conn: &mut Conn) -> Result<Authenticated<String>, LoginError> {
+        let frame = next_frame(conn).await?;
+        let username = frame.parse::<PreAuthReq>()?.username.to_owned();
+        let user = match self.creds.get_user_data(&username).await {
+            Some(user) => user,
+            None => UserData::decoy(&username, &self.decoy_secret),
+        };
+
+        let mut auth = SrpAuth::<(), String>::new();
+        let expires = Instant::now() + self.timeout;
+        let step1 = blocking(move || Ok(Step1::new(&frame.parse()?, &user, username, expires)?)).await?;
+        conn.send(&auth.insert((), step1)).await?;
+
+        let frame = next_frame(conn).await?;
+        let step1 = auth.take(&frame.parse()?, (), Instant::now())?;
+        let authenticated = blocking(move || Ok(step1.step2(frame.parse()?)?)).await?;
+        conn.send(&authenticated.response()).await?;
+        Ok(authenticated)
+    }
+}
+
+/// Log in on `stream` and return the shared key once the server proved
+/// that it knows the verifier.
+pub async fn login(stream: TcpStream, username: &str, password: &str) -> Result<[u8; 512], LoginError> {
+    let mut conn = Framed::new(stream, MessageCodec);
+    let client = ClientStart::new(rand_num::<{ U4096::LIMBS }>().to_le_bytes());
+    conn.send(&client.message(username)).await?;
+
+    let frame = next_frame(&mut conn).await?;
+    let client = client.process(&frame.parse()?, username, password)?;
+    conn.send(&client.message()).await?;
+
+    let frame = next_frame(&mut conn).await?;
+    Ok(client.finish(&frame.parse()?)?)
+}
+
+#[cfg(test)]
+struct Users(UserData);
+#[cfg(test)]
+impl CredHasher for Users {
+    async fn get_user_data(&self, username: &str) -> Option<UserData> {
+        match username {
+            "user" => Some(self.0.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_tcp_login() {
+    use crate::ErrorCode;
+    use rand::random;
+
+    let server = Server::new(Users(UserData::test_user("password")), random());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    for (username, password, ok) in [("user", "password", true), ("user", "wrong", false), ("nobody", "password", false)] {
+        let client = async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            login(stream, username, password).await
+        };
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            server.handle(stream).await
+        };
+        let (client, server) = tokio::join!(client, server);
+        match (client, server) {
+            (Ok(key), Ok(auth)) if ok => {
+                assert_eq!(key, auth.get_key());
+                assert_eq!(auth.data, "user");
+            }
+            (Err(LoginError::Rejected(resp)), Err(LoginError::Auth(_))) if !ok => {
+                assert_eq!(resp.code, ErrorCode::AuthFailed);
+            }
+            (client, server) => panic!("{username}/{password}: {:?} {:?}", client.err(), server.err()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_slow_client() {
+    use rand::random;
+    use std::sync::mpsc;
+
+    let server = Server::new(Users(UserData::test_user("password")), random());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let run = Arc::new(server).run(listener, move |result| tx.send(result.is_ok()).unwrap());
+
+    // a client that never sends anything does not hold up the next login
+    let clients = async {
+        let _idle = TcpStream::connect(addr).await.unwrap();
+        let key = login(TcpStream::connect(addr).await.unwrap(), "user", "password").await;
+        assert!(key.is_ok());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    tokio::select! {
+        result = run => panic!("{result:?}"),
+        () = clients => {}
+    }
+    assert_eq!(rx.try_recv(), Ok(true));
+}
@@ -0,0 +1,367 @@
+//! TOTP second factor, RFC 6238.
+//!
+//! Enroll a user with `Totp::generate`, show `Totp::uri` as a QR code and put
+//! the secret into the record with `UserData::set_totp`, which seals it under
+//! a key of the server. The login of such a user does not end at
+//! `SrpAuth::auth`: hand the `Authenticated` to `TotpLogins::challenge` and
+//! send the `TotpChallenge` in place of the `AuthResponse`. The client answers
+//! with a `TotpReq`, and once `TotpLogins::verify` accepts the code, the
+//! `AuthResponse` goes out. Endpoints of your own can let a login from a
+//! trusted device skip the code, see `device`.
+//!
+//! Codes are HMAC-SHA-1, HMAC-SHA-256 or HMAC-SHA-512. `Totp::generate` uses
+//! SHA-1: many authenticator apps ignore the `algorithm` of the URI and
+//! compute SHA-1 codes anyway, and the weaknesses of SHA-1 do not carry over
+//! to HMAC. Each code is accepted once. Times are seconds since the Unix epoch.
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+};
+
+pub use auth_common::{TotpChallenge, TotpReq};
+use auth_common::ErrorCode;
+use blake2::{Blake2b512, Digest};
+use chacha20poly1305::{
+    ChaCha20Poly1305, KeyInit,
+    aead::{Aead, Payload},
+};
+use hmac::{Mac, SimpleHmac};
+use rand::random;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::Authenticated;
+
+/// Wrong codes allowed per login before it has to start over.
+pub const MAX_ATTEMPTS: u8 = 3;
+
+/// Stored by its name in the URI, e.g. `SHA1`, so sealed records do not
+/// depend on the order of the variants.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+}
+impl From<Algorithm> for &'static str {
+    fn from(algorithm: Algorithm) -> Self {
+        algorithm.name()
+    }
+}
+impl TryFrom<String> for Algorithm {
+    type Error = String;
+    fn try_from(name: String) -> Result<Self, String> {
+        [Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512]
+            .into_iter()
+            .find(|a| a.name() == name)
+            .ok_or_else(|| format!("unknown algorithm {name}"))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TotpError {
+    /// No login waits for this code, or it took too long.
+    Expired,
+    /// Wrong code.
+    Invalid,
+    /// The code was accepted before.
+    Replayed,
+    /// The sealed secret does not open: wrong key or a modified record.
+    Sealed,
+    /// `Totp::new` got digits outside 6 to 9, or a period of zero.
+    Parameters,
+}
+impl TotpError {
+    pub fn code(self) -> ErrorCode {
+        match self {
+            TotpError::Expired => ErrorCode::HandshakeExpired,
+            TotpError::Invalid | TotpError::Replayed => ErrorCode::AuthFailed,
+            TotpError::Sealed | TotpError::Parameters => ErrorCode::Internal,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Totp {
+    secret: Vec<u8>,
+    algorithm: Algorithm,
+    digits: u8,
+    period: u64,
+}
+/// Does not print the secret.
+impl std::fmt::Debug for Totp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Totp")
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .finish_non_exhaustive()
+    }
+}
+impl Totp {
+    /// A random 20 byte secret, SHA-1, six digits every 30 seconds, which
+    /// every authenticator app supports.
+    pub fn generate() -> Totp {
+        Totp::new(random::<[u8; 20]>().to_vec(), Algorithm::Sha1, 6, 30).expect("parameters are in range")
+    }
+    /// `digits` has to be 6 to 9 and `period` more than zero.
+    pub fn new(secret: Vec<u8>, algorithm: Algorithm, digits: u8, period: u64) -> Result<Totp, TotpError> {
+        if !(6..=9).contains(&digits) || period == 0 {
+            return Err(TotpError::Parameters);
+        }
+        Ok(Totp { secret, algorithm, digits, period })
+    }
+    pub fn digits(&self) -> u8 {
+        self.digits
+    }
+    /// The secret in base32, for typing it into the app by hand.
+    pub fn secret_base32(&self) -> String {
+        base32(&self.secret)
+    }
+    /// `otpauth://` URI for authenticator apps, usually shown as a QR code.
+    pub fn uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm={}&digits={}&period={}",
+            percent_encode(issuer),
+            percent_encode(account),
+            self.secret_base32(),
+            percent_encode(issuer),
+            self.algorithm.name(),
+            self.digits,
+            self.period,
+        )
+    }
+
+    /// The code at time `now`.
+    pub fn code(&self, now: u64) -> u32 {
+        self.code_at(now / self.period)
+    }
+    /// The time step `code` belongs to, if it is at most `drift` steps before
+    /// or after the one of `now`.
+    pub fn check(&self, code: u32, now: u64, drift: u64) -> Option<u64> {
+        let step = now / self.period;
+        // no early return, every step in the window is computed
+        let mut found = None;
+        for s in step.saturating_sub(drift)..=step.saturating_add(drift) {
+            if self.code_at(s) == code {
+                found = Some(s);
+            }
+        }
+        found
+    }
+
+    /// HOTP of RFC 4226 for counter `step`.
+    fn code_at(&self, step: u64) -> u32 {
+        let counter = step.to_be_bytes();
+        let hash = match self.algorithm {
+            Algorithm::Sha1 => hmac::<Sha1>(&self.secret, &counter),
+            Algorithm::Sha256 => hmac::<Sha256>(&self.secret, &counter),
+            Algorithm::Sha512 => hmac::<Sha512>(&self.secret, &counter),
+        };
+        let offset = (hash[hash.len() - 1] & 0xf) as usize;
+        let value = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+        value % 10u32.pow(self.digits as u32)
+    }
+    /// Identifies the secret among the used codes, without keeping it.
+    fn fingerprint(&self) -> [u8; 16] {
+        let hash = Blake2b512::new().chain_update(b"auth totp").chain_update(&self.secret).finalize();
+        hash[..16].try_into().unwrap()
+    }
+}
+
+fn hmac<H: Digest + hmac::digest::core_api::BlockSizeUser>(key: &[u8], msg: &[u8]) -> Vec<u8> {
+    let mut mac = <SimpleHmac<H> as Mac>::new_from_slice(key).expect("any key length works");
+    mac.update(msg);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// RFC 4648 base32 without padding, as authenticator apps expect it.
+fn base32(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut out = String::new();
+    let (mut buf, mut bits) = (0u32, 0);
+    for &b in data {
+        buf = (buf << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[(buf >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[(buf << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// A `Totp` sealed with ChaCha20-Poly1305, as stored in `UserData`:
+///
+/// | nonce (12) | ciphertext + tag |
+///
+/// The salt of the record is the associated data, so the secret can not be
+/// moved to another user.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SealedTotp(#[serde(with = "serdapt_base64::StdBase64")] Vec<u8>);
+impl SealedTotp {
+    pub(crate) fn seal(totp: &Totp, key: &[u8; 32], salt: &[u8; 32]) -> SealedTotp {
+        let plain = postcard::to_allocvec(totp).expect("totp serializes");
+        let nonce: [u8; 12] = random();
+        let mut sealed = nonce.to_vec();
+        let cipher = ChaCha20Poly1305::new(key.into());
+        sealed.extend(cipher.encrypt(&nonce.into(), Payload { msg: &plain, aad: salt }).expect("plaintext is short"));
+        SealedTotp(sealed)
+    }
+    pub(crate) fn open(&self, key: &[u8; 32], salt: &[u8; 32]) -> Result<Totp, TotpError> {
+        let (nonce, sealed) = self.0.split_at_checked(12).ok_or(TotpError::Sealed)?;
+        let cipher = ChaCha20Poly1305::new(key.into());
+        let plain = cipher.decrypt(nonce.into(), Payload { msg: sealed, aad: salt }).map_err(|_| TotpError::Sealed)?;
+        postcard::from_bytes(&plain).map_err(|_| TotpError::Sealed)
+    }
+}
+
+struct Pending<D> {
+    auth: Authenticated<D>,
+    totp: Totp,
+    expires: u64,
+    attempts: u8,
+}
+
+/// Logins that passed SRP and wait for a TOTP code, keyed like `SrpAuth`.
+pub struct TotpLogins<K, D> {
+    logins: HashMap<(K, u64), Pending<D>>,
+    /// `(valid until, secret, step)` of accepted codes.
+    used: BTreeSet<(u64, [u8; 16], u64)>,
+    drift: u64,
+}
+impl<K: Eq + Hash, D> Default for TotpLogins<K, D> {
+    fn default() -> Self {
+        TotpLogins::new()
+    }
+}
+impl<K: Eq + Hash, D> TotpLogins<K, D> {
+    /// Codes of the previous and next time step pass as well, see `with_drift`.
+    pub fn new() -> Self {
+        TotpLogins { logins: HashMap::new(), used: BTreeSet::new(), drift: 1 }
+    }
+    /// Accept codes up to `steps` time steps off, for clocks that drift.
+    pub fn with_drift(self, steps: u64) -> Self {
+        TotpLogins { drift: steps, ..self }
+    }
+
+    /// Hold back `auth` until the code of `totp` is entered, until `expires`.
+    pub fn challenge(&mut self, auth: Authenticated<D>, totp: Totp, key1: K, expires: u64) -> TotpChallenge {
+        let key2: u64 = random();
+        let digits = totp.digits;
+        self.logins.insert((key1, key2), Pending { auth, totp, expires, attempts: 0 });
+        TotpChallenge { key: key2.to_le_bytes(), digits }
+    }
+    /// Check the code. A wrong one can be retried `MAX_ATTEMPTS` times in total.
+    pub fn verify(&mut self, req: TotpReq, key1: K, now: u64) -> Result<Authenticated<D>, TotpError> {
+        let key = (key1, u64::from_le_bytes(req.key));
+        let mut pending = self.logins.remove(&key).ok_or(TotpError::Expired)?;
+        if now >= pending.expires {
+            return Err(TotpError::Expired);
+        }
+        let result = match pending.totp.check(req.code, now, self.drift) {
+            None => Err(TotpError::Invalid),
+            Some(step) => {
+                self.clean(now);
+                let valid_until = (step + self.drift + 1).saturating_mul(pending.totp.period);
+                match self.used.insert((valid_until, pending.totp.fingerprint(), step)) {
+                    true => Ok(()),
+                    false => Err(TotpError::Replayed),
+                }
+            }
+        };
+        match result {
+            Ok(()) => Ok(pending.auth),
+            Err(e) => {
+                pending.attempts += 1;
+                if pending.attempts < MAX_ATTEMPTS {
+                    self.logins.insert(key, pending);
+                }
+                Err(e)
+            }
+        }
+    }
+    /// Drop expired logins and codes that are too old to be accepted anyway.
+    pub fn clean(&mut self, now: u64) {
+        self.logins.retain(|_, p| p.expires > now);
+        self.used = self.used.split_off(&(now, [0; 16], 0));
+    }
+}
+
+#[test]
+fn test_rfc6238() {
+    // Appendix B, with the seeds of the reference implementation
+    let sha1 = Totp::new(b"12345678901234567890".to_vec(), Algorithm::Sha1, 8, 30).unwrap();
+    let sha256 = Totp::new(b"12345678901234567890123456789012".to_vec(), Algorithm::Sha256, 8, 30).unwrap();
+    let sha512 = Totp::new(b"1234567890123456789012345678901234567890123456789012345678901234".to_vec(), Algorithm::Sha512, 8, 30).unwrap();
+    let vectors = [
+        (59, 94287082, 46119246, 90693936),
+        (1111111109, 7081804, 68084774, 25091201),
+        (1111111111, 14050471, 67062674, 99943326),
+        (1234567890, 89005924, 91819424, 93441116),
+        (2000000000, 69279037, 90698825, 38618901),
+        (20000000000, 65353130, 77737706, 47863826),
+    ];
+    for (time, a, b, c) in vectors {
+        assert_eq!(sha1.code(time), a);
+        assert_eq!(sha256.code(time), b);
+        assert_eq!(sha512.code(time), c);
+    }
+
+    assert_eq!(sha256.check(46119246, 59 + 30, 1), Some(1));
+    assert_eq!(sha256.check(46119246, 59 + 60, 1), None);
+
+    // RFC 4648 section 10
+    assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+    assert_eq!(base32(b"fooba"), "MZXW6YTB");
+    let totp = Totp::new(b"foobar".to_vec(), Algorithm::Sha256, 6, 30).unwrap();
+    assert_eq!(
+        totp.uri("Example Co", "user@example.com"),
+        "otpauth://totp/Example%20Co:user%40example.com?secret=MZXW6YTBOI&issuer=Example%20Co&algorithm=SHA256&digits=6&period=30"
+    );
+
+    for (digits, period) in [(5, 30), (10, 30), (6, 0)] {
+        assert_eq!(Totp::new(b"foobar".to_vec(), Algorithm::Sha1, digits, period), Err(TotpError::Parameters));
+    }
+}
+
+#[test]
+fn test_sealed() {
+    // stored by name, whatever the order of the variants
+    assert_eq!(Algorithm::default(), Algorithm::Sha1);
+    assert_eq!(postcard::to_allocvec(&Algorithm::Sha256).unwrap(), b"\x06SHA256");
+    assert_eq!(postcard::from_bytes::<Algorithm>(b"\x04SHA1"), Ok(Algorithm::Sha1));
+    assert!(postcard::from_bytes::<Algorithm>(b"\x03MD5").is_err());
+
+    let totp = Totp::generate();
+    let sealed = SealedTotp::seal(&totp, &[1; 32], &[5; 32]);
+    assert_eq!(sealed.open(&[1; 32], &[5; 32]), Ok(totp));
+    assert_eq!(sealed.open(&[2; 32], &[5; 32]), Err(TotpError::Sealed));
+    // bound to the record of the user
+    assert_eq!(sealed.open(&[1; 32], &[6; 32]), Err(TotpError::Sealed));
+}
@@ -1,12 +1,11 @@
-use auth_common::{Data, RegisterReq};
+use auth_common::{RegisterReq, Suite, encode_message, recovery::{RecoveryCodes, RecoveryKey}};
 use base64::{Engine, prelude::BASE64_STANDARD};
-use blake2::Blake2b512;
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 use rand::Rng;
-use srp::{A2, U4096, Uint, client::SrpClient4096};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(group(ArgGroup::new("recovery").args(["recovery_codes", "recovery_key"]).multiple(true)))]
 struct Args {
     /// Name of the person to greet
     #[arg(short, long)]
@@ -15,14 +14,27 @@ struct Args {
     /// Number of times to greet
     #[arg(short, long)]
     pass: Option<String>,
-}
 
-type Digest = Blake2b512;
+    /// Algorithm suite tag (1: Blake2b + Argon2d, 2: Blake2s + Argon2id)
+    #[arg(short, long, default_value_t = Suite::default() as u8)]
+    suite: u8,
+
+    /// Also generate this many single-use recovery codes
+    #[arg(short, long)]
+    recovery_codes: Option<usize>,
 
+    /// Also generate a recovery key, printed with the `RegisterReq` of its verifier
+    #[arg(short = 'k', long)]
+    recovery_key: bool,
 
-fn register_with_username(username: &str, password: &str) {
+    /// Only generate new recovery codes or a new key, keep the password
+    #[arg(long, requires = "recovery")]
+    codes_only: bool,
+}
+
+fn register_with_username(username: &str, password: &str, suite: Suite) {
     let salt: [u8; 32] = rand::random();
-    let verifier = SrpClient4096::<A2, Digest>::compute_verifier(
+    let verifier = suite.compute_verifier(
         username.as_bytes(),
         &password.as_bytes(),
         &salt,
@@ -30,22 +42,44 @@ fn register_with_username(username: &str, password: &str) {
 
     let mut buf = [0; 1024];
 
-    let encoded = RegisterReq {
+    let req = RegisterReq {
         username,
         salt: salt.into(),
-        verifier: verifier.to_le_bytes().into(),
-    }
-    .encode(&mut buf)
-    .unwrap();
+        verifier: &verifier,
+        suite,
+    };
+    let encoded = encode_message(&req, &mut buf).unwrap();
 
     println!("{}", BASE64_STANDARD.encode(&encoded));
 }
 
+/// Prints the codes for the user, then the hashes for the `recovery` field of
+/// the stored record.
+fn recovery_codes(count: usize) {
+    let (codes, hashes) = RecoveryCodes::generate(&mut rand::rng(), count);
+    for code in &codes {
+        println!("{code}");
+    }
+    println!("{}", serde_json::to_string(&hashes).unwrap());
+}
+
 fn main() {
     let args = Args::parse();
+    let suite = Suite::from_u8(args.suite).expect("unknown suite");
+    if let Some(count) = args.recovery_codes {
+        recovery_codes(count);
+    }
+    if args.recovery_key {
+        let key = RecoveryKey::from_bytes(rand::random());
+        println!("{key}");
+        register_with_username(&args.user, key.as_str(), suite);
+    }
+    if args.codes_only {
+        return;
+    }
     let pass = match args.pass {
         Some(pw) => pw,
         None => rpassword::prompt_password("Password: ").unwrap(),
     };
-    register_with_username(&args.user, &pass);
+    register_with_username(&args.user, &pass, suite);
 }
@@ -5,10 +5,10 @@ use crypto_bigint::{
     ConcatMixed, Encoding, U256, U512, U4096, Uint, WideningMul, Zero, const_monty_form,
 };
 use digest::{Digest, Output, OutputSizeUser};
-use subtle::ConstantTimeEq;
+use subtle::{ConditionallySelectable, ConstantTimeEq};
 
 use crate::DigestNum;
-use crate::groups::g_4096::{G_MOD_N, ModN, N, mod_n};
+use crate::groups::g_4096::{ConstMontyModN, G_MOD_N, ModN, N, mod_n};
 use crate::SrpAuthError;
 use crate::utils::{compute_m1, compute_m2, mod4096};
 
@@ -62,11 +62,16 @@ where
     }
     /// Get password verifier (v in RFC5054) for user registration on the server.
     pub fn compute_verifier(username: &[u8], password: &[u8], salt: &[u8]) -> U4096 {
-        let x = U256::from_be_bytes(P::hash_user_password(username, password, salt));
+        let x = Self::compute_x(username, password, salt);
         Self::compute_v(&x)
     }
-    // v = g^x % N
-    fn compute_v(x: &U256) -> U4096 {
+    /// Private key x derived from the password. This is the slow part of
+    /// `process_reply`, split out so callers can yield in between.
+    pub fn compute_x(username: &[u8], password: &[u8], salt: &[u8]) -> U256 {
+        U256::from_be_bytes(P::hash_user_password(username, password, salt))
+    }
+    /// v = g^x % N. The client needs it for the premaster secret as well.
+    pub fn compute_v(x: &U256) -> U4096 {
         G_MOD_N.pow(x).retrieve()
     }
 
@@ -81,19 +86,39 @@ where
         salt: &[u8],
         b_pub: &U4096,
     ) -> Result<SrpClientVerifier4096<D>, SrpAuthError> {
-        let a_pub = self.compute_a_pub();
-
-        // Safeguard against malicious B
-        if mod_n(b_pub).is_zero().into() {
-            return Err(SrpAuthError::IllegalParameter);
-        }
+        let x = Self::compute_x(username, password, salt);
+        self.process_reply_precomputed(&self.compute_a_pub(), &x, &Self::compute_v(&x), b_pub)
+    }
 
-        let u = mod4096::compute_u::<D>(&a_pub, &b_pub).to_num();
-        let k = mod4096::compute_k::<D>().to_num();
-        let x = U256::from_be_bytes(P::hash_user_password(username, password, salt));
+    /// `process_reply` in steps: `a_pub` from `compute_a_pub`, `x` from
+    /// `compute_x` and `g_x` from `compute_v`. Only `a_pub` saves work, it is
+    /// not computed again. The others let the caller yield in between.
+    pub fn process_reply_precomputed(
+        &self,
+        a_pub: &U4096,
+        x: &U256,
+        g_x: &U4096,
+        b_pub: &U4096,
+    ) -> Result<SrpClientVerifier4096<D>, SrpAuthError> {
+        let (base, exp) = self.premaster_base(a_pub, x, g_x, b_pub)?;
+        Ok(Self::verifier(a_pub, b_pub, &base.pow(&exp).retrieve()))
+    }
 
-        let key = self.compute_premaster_secret(&b_pub, &k, &x, &u);
+    /// The premaster secret of `process_reply_precomputed` as `PowSteps`, to
+    /// pause between the steps. Pass the result to `verifier`.
+    pub fn premaster_steps(
+        &self,
+        a_pub: &U4096,
+        x: &U256,
+        g_x: &U4096,
+        b_pub: &U4096,
+    ) -> Result<PowSteps, SrpAuthError> {
+        let (base, exp) = self.premaster_base(a_pub, x, g_x, b_pub)?;
+        Ok(PowSteps::new(&base.retrieve(), &exp.to_be_bytes()))
+    }
 
+    /// The proofs for the premaster secret `key` from `premaster_steps`.
+    pub fn verifier(a_pub: &U4096, b_pub: &U4096, key: &U4096) -> SrpClientVerifier4096<D> {
         let m1 = compute_m1::<D>(
             &a_pub.to_be_bytes(),
             &b_pub.to_be_bytes(),
@@ -102,10 +127,34 @@ where
 
         let m2 = compute_m2::<D>(&a_pub.to_be_bytes(), &m1, &key.to_be_bytes());
 
-        Ok(SrpClientVerifier4096 { m1, m2, key })
+        SrpClientVerifier4096 { m1, m2, key: *key }
+    }
+
+    // (B - (k * g^x)) and (a + (u * x)), the premaster secret is the one to the other
+    fn premaster_base(
+        &self,
+        a_pub: &U4096,
+        x: &U256,
+        g_x: &U4096,
+        b_pub: &U4096,
+    ) -> Result<(ConstMontyModN, U4096), SrpAuthError> {
+        // Safeguard against malicious B
+        if mod_n(b_pub).is_zero().into() {
+            return Err(SrpAuthError::IllegalParameter);
+        }
+
+        let u: Uint<D_N> = mod4096::compute_u::<D>(a_pub, b_pub).to_num();
+        let k: Uint<D_N> = mod4096::compute_k::<D>().to_num();
+
+        // Because we do operation in modulo N we can get: b_pub > base. That's not good. So we add N to b_pub to make sure.
+        // B - k (g^x)
+        let base = mod_n(b_pub) - mod_n(g_x).mul(&mod_n(&k.resize()));
+        let exp = u.resize::<L>() * x.resize::<L>() + self.a;
+        Ok((base, exp))
     }
 
     // (B - (k * g^x)) ^ (a + (u * x)) % N
+    #[deprecated(note = "use `process_reply_precomputed` or `premaster_steps`")]
     pub fn compute_premaster_secret(
         &self,
         b_pub: &U4096,
@@ -113,17 +162,70 @@ where
         x: &U256,
         u: &Uint<D_N>,
     ) -> U4096 {
-        // Because we do operation in modulo N we can get: b_pub > base. That's not good. So we add N to b_pub to make sure.
-        // B - k (g^x)
         let base = mod_n(b_pub) - G_MOD_N.pow(x).mul(&mod_n(&k.resize()));
         let exp = u.resize::<L>() * x.resize::<L>() + self.a;
-        // S = (B - kg^x) ^ (a + ux)
-        // or
-        // S = base ^ exp
         base.pow(&exp).retrieve()
     }
 }
 
+/// `base^exp % N`, a few bytes of the exponent at a time, so the caller can
+/// pause in between, e.g. to let an event loop run. Like `pow`, the work
+/// depends on the length of the exponent, not on its value.
+pub struct PowSteps {
+    /// base^0 to base^15, for 4-bit windows of the exponent
+    table: [ConstMontyModN; 16],
+    /// big endian, `len` bytes
+    exp: [u8; U4096::BYTES],
+    len: usize,
+    done: usize,
+    acc: ConstMontyModN,
+}
+impl PowSteps {
+    /// Bytes of the exponent each `step` works through, 1/16 of a full exponent.
+    pub const STEP_BYTES: usize = 32;
+
+    /// `exp` is big endian and at most 512 bytes long.
+    pub fn new(base: &U4096, exp: &[u8]) -> Self {
+        let base = mod_n(base);
+        let mut table = [ConstMontyModN::ONE; 16];
+        for i in 1..table.len() {
+            table[i] = table[i - 1] * base;
+        }
+        let mut buf = [0; U4096::BYTES];
+        buf[..exp.len()].copy_from_slice(exp);
+        PowSteps { table, exp: buf, len: exp.len(), done: 0, acc: ConstMontyModN::ONE }
+    }
+    /// Number of `step` calls until the result is ready.
+    pub fn steps_left(&self) -> usize {
+        (self.len - self.done).div_ceil(Self::STEP_BYTES)
+    }
+    /// Work through the next `STEP_BYTES` of the exponent.
+    pub fn step(&mut self) {
+        let end = usize::min(self.done + Self::STEP_BYTES, self.len);
+        for &byte in &self.exp[self.done..end] {
+            for window in [byte >> 4, byte & 0xf] {
+                for _ in 0..4 {
+                    self.acc = self.acc.square();
+                }
+                // read every entry, so the access pattern does not leak the window
+                let mut factor = self.table[0];
+                for (i, entry) in self.table.iter().enumerate() {
+                    factor = ConstMontyModN::conditional_select(&factor, entry, (i as u8).ct_eq(&window));
+                }
+                self.acc *= factor;
+            }
+        }
+        self.done = end;
+    }
+    /// Run the remaining steps and return the result.
+    pub fn finish(mut self) -> U4096 {
+        while self.steps_left() > 0 {
+            self.step();
+        }
+        self.acc.retrieve()
+    }
+}
+
 impl<D: Digest> SrpClientVerifier4096<D> {
     /// Get shared secret key without authenticating server, e.g. for using with
     /// authenticated encryption modes. DO NOT USE this method without
@@ -137,6 +239,12 @@ impl<D: Digest> SrpClientVerifier4096<D> {
         &self.m1
     }
 
+    /// The proof the server is expected to reply with, for checking it later
+    /// with a constant time comparison of its own.
+    pub fn server_proof(&self) -> &Output<D> {
+        &self.m2
+    }
+
     /// Verify server reply to verification data.
     pub fn verify_server(&self, reply: &[u8]) -> Result<(), SrpAuthError> {
         if self.m2.ct_eq(reply).unwrap_u8() != 1 {
@@ -147,3 +255,20 @@ impl<D: Digest> SrpClientVerifier4096<D> {
         }
     }
 }
+
+#[test]
+fn test_pow_steps() {
+    let base = U4096::from_u64(0x1234_5678_9abc_def0);
+    let exp = U4096::from_be_bytes([0xa5; U4096::BYTES]);
+    let mut steps = PowSteps::new(&base, &exp.to_be_bytes());
+    assert_eq!(steps.steps_left(), 16);
+    steps.step();
+    assert_eq!(steps.steps_left(), 15);
+    assert_eq!(steps.finish(), mod_n(&base).pow(&exp).retrieve());
+
+    // short exponents take fewer steps
+    let x = U256::from_u64(0xdead_beef);
+    let steps = PowSteps::new(&G_MOD_N.retrieve(), &x.to_be_bytes());
+    assert_eq!(steps.steps_left(), 1);
+    assert_eq!(steps.finish(), G_MOD_N.pow(&x).retrieve());
+}
@@ -59,3 +59,27 @@ impl client::UserPasswordHasher for A2 {
         out
     }
 }
+
+/// Argon2id with the parameters recommended by OWASP (19 MiB, 2 passes).
+pub struct A2id;
+impl client::UserPasswordHasher for A2id {
+    type Out = [u8; 32];
+    fn hash_user_password(username: &[u8], password: &[u8], salt: &[u8]) -> Self::Out {
+        const P: Params = match Params::new(19 * 1024, 2, 1, Some(32)) {
+            Ok(p) => p,
+            _ => panic!(),
+        };
+
+        let mut out = [0; 32];
+        Argon2::new_with_secret(
+            username,
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            P,
+        )
+        .unwrap()
+        .hash_password_into(password, salt, &mut out)
+        .unwrap();
+        out
+    }
+}